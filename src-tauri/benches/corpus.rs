@@ -0,0 +1,35 @@
+/*
+Encode and decode throughput of Qross, the image crate's QOI codec and PNG over the synthetic corpus.
+Bytes per pixel are printed once per image, throughput is reported by criterion in MB/s of raw RGB.
+Run with 'cargo bench --bench corpus'.
+*/
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::RgbImage;
+
+use image_compressor::bench::{corpus, Codec};
+
+fn bench_corpus(c: &mut Criterion) {
+    for (name, img) in corpus() {
+        let raw: u64 = img.as_raw().len() as u64;
+
+        let mut group = c.benchmark_group(format!("corpus_{}", name));
+        group.throughput(Throughput::Bytes(raw));
+
+        for codec in Codec::ALL {
+            let encoded: Vec<u8> = codec.encode(&img).unwrap();
+            println!("{} {}: {:.3} bytes per pixel", name, codec.name(), encoded.len() as f64 / (raw / 3) as f64);
+
+            group.bench_with_input(BenchmarkId::new("encode", codec.name()), &img, |b, img: &RgbImage| {
+                b.iter(|| codec.encode(img).unwrap())
+            });
+            group.bench_with_input(BenchmarkId::new("decode", codec.name()), &encoded, |b, encoded: &Vec<u8>| {
+                b.iter(|| codec.decode(encoded).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_corpus);
+criterion_main!(benches);
@@ -0,0 +1,119 @@
+/*
+Encoder throughput against the reference C implementation, the 'qoi' crate and the image crate's QOI writer.
+Qross drops alpha, so the codecs are compared on RGB. An RGBA source is only timed for Qross, against its own RGB run.
+Run with 'cargo bench --bench encode', the C encoder is included with
+'QOI_C_DIR=<checkout of phoboslab/qoi> cargo bench --bench encode --features c-reference'.
+*/
+
+use std::io;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, ImageEncoder, RgbImage};
+use image::codecs::qoi::QoiEncoder;
+
+use image_compressor::comp::{Data, QoiEncode};
+use image_compressor::options::CompressOptions;
+use image_compressor::metadata::Metadata;
+
+// qoi.h, compiled by build.rs.
+#[cfg(feature = "c-reference")]
+mod reference {
+    use std::ffi::{c_int, c_uint, c_void};
+
+    #[repr(C)]
+    struct QoiDesc {
+        width: c_uint,
+        height: c_uint,
+        channels: u8,
+        colorspace: u8,
+    }
+
+    extern "C" {
+        fn qoi_encode(data: *const c_void, desc: *const QoiDesc, out_len: *mut c_int) -> *mut c_void;
+        fn free(ptr: *mut c_void);
+    }
+
+    // Encodes the RGB 'pixels' with the C encoder, returns the size of the stream.
+    pub fn encode(pixels: &[u8], width: u32, height: u32) -> usize {
+        assert_eq!(pixels.len(), width as usize * height as usize * 3);
+        let desc: QoiDesc = QoiDesc { width, height, channels: 3, colorspace: 0 };
+        let mut len: c_int = 0;
+
+        // qoi_encode reads width * height * channels bytes and mallocs its output, freed right away.
+        unsafe {
+            let out: *mut c_void = qoi_encode(pixels.as_ptr().cast(), &desc, &mut len);
+            assert!(!out.is_null(), "qoi_encode failed");
+            free(out);
+        }
+        len as usize
+    }
+}
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+// Flat blocks with a noisy gradient in between, like a screenshot with a photo in it.
+fn synthetic_image() -> RgbImage {
+    let mut seed: u32 = 0x9E3779B9;
+    RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if (x / 128 + y / 128) % 3 == 0 {
+            image::Rgb([40, 44, 52])
+        } else {
+            let noise: u8 = (seed & 0x7) as u8;
+            image::Rgb([(x / 8) as u8 + noise, (y / 5) as u8, ((x + y) / 13) as u8 ^ noise])
+        }
+    })
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let rgb: DynamicImage = DynamicImage::ImageRgb8(synthetic_image());
+    let rgba: DynamicImage = DynamicImage::ImageRgba8(rgb.to_rgba8());
+
+    let mut group = c.benchmark_group("encode_1080p");
+    group.throughput(Throughput::Bytes((WIDTH * HEIGHT * 3) as u64));
+
+    for img in [rgb, rgba] {
+        let layout: String = format!("{:?}", img.color());
+        let has_alpha: bool = img.color().has_alpha();
+        let data: Data = Data {
+            path: String::from("synthetic"),
+            img,
+            options: CompressOptions::default(),
+            metadata: Metadata::default(),
+            sequence: None,
+        };
+
+        group.bench_with_input(BenchmarkId::new("qross", &layout), &data, |b, data| {
+            b.iter(|| {
+                let (pixels, channels) = data.pixel_slice();
+                data.encode(&pixels, channels, &mut io::sink()).unwrap()
+            })
+        });
+        // The others would write 4 channels where Qross writes 3.
+        if has_alpha {
+            continue;
+        }
+        #[cfg(feature = "c-reference")]
+        group.bench_with_input(BenchmarkId::new("reference-c", &layout), &data, |b, data| {
+            b.iter(|| reference::encode(data.img.as_bytes(), WIDTH, HEIGHT))
+        });
+        group.bench_with_input(BenchmarkId::new("qoi", &layout), &data, |b, data| {
+            b.iter(|| qoi::encode_to_vec(data.img.as_bytes(), WIDTH, HEIGHT).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("image", &layout), &data, |b, data| {
+            b.iter(|| {
+                let mut out: Vec<u8> = Vec::new();
+                QoiEncoder::new(&mut out)
+                    .write_image(data.img.as_bytes(), WIDTH, HEIGHT, data.img.color())
+                    .unwrap();
+                out
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
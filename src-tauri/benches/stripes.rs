@@ -0,0 +1,60 @@
+/*
+Serial vs striped encoding of a single 8K image.
+Run with 'cargo bench --bench stripes'.
+*/
+
+use std::fs::File;
+use std::io::BufWriter;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use image::{DynamicImage, RgbImage};
+
+use image_compressor::comp::{Data, QoiEncode};
+use image_compressor::colorspace::ColorSpace;
+use image_compressor::options::CompressOptions;
+use image_compressor::metadata::Metadata;
+use image_compressor::stripes::{encode_striped, stripe_rows};
+
+const WIDTH: u32 = 7680;
+const HEIGHT: u32 = 4320;
+
+// Gradient with a little deterministic noise, so every op gets used.
+fn synthetic_image() -> RgbImage {
+    let mut seed: u32 = 0x2545F491;
+    RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let noise: u8 = (seed & 0x3) as u8;
+        image::Rgb([(x / 30) as u8 ^ noise, (y / 17) as u8, ((x + y) / 47) as u8 + noise])
+    })
+}
+
+fn output() -> BufWriter<File> {
+    BufWriter::new(File::create(std::env::temp_dir().join("qross_bench.qoi")).unwrap())
+}
+
+fn bench_stripes(c: &mut Criterion) {
+    let data: Data = Data {
+        path: String::from("synthetic"),
+        img: DynamicImage::ImageRgb8(synthetic_image()),
+        options: CompressOptions::default(),
+        metadata: Metadata::default(),
+        sequence: None,
+    };
+    let pixels: Vec<u8> = data.get_pixels();
+
+    let mut group = c.benchmark_group("encode_8k");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(pixels.len() as u64));
+
+    group.bench_function("serial", |b| {
+        b.iter(|| data.encode(&pixels, 3, &mut output()).unwrap())
+    });
+    group.bench_function("striped", |b| {
+        b.iter(|| encode_striped(&pixels, 3, WIDTH, HEIGHT, ColorSpace::Srgb, stripe_rows(HEIGHT), &mut output()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_stripes);
+criterion_main!(benches);
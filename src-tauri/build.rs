@@ -1,3 +1,27 @@
 fn main() {
+  #[cfg(feature = "c-reference")]
+  build_c_reference();
   tauri_build::build()
 }
+
+/*
+Compiles the reference C implementation for 'benches/encode.rs'.
+QOI_C_DIR points at a checkout of https://github.com/phoboslab/qoi, qoi.h isn't kept in this repo.
+ */
+#[cfg(feature = "c-reference")]
+fn build_c_reference() {
+  use std::path::PathBuf;
+
+  println!("cargo:rerun-if-env-changed=QOI_C_DIR");
+  let dir: PathBuf = std::env::var_os("QOI_C_DIR")
+    .map(PathBuf::from)
+    .expect("the c-reference feature needs QOI_C_DIR, a checkout of https://github.com/phoboslab/qoi");
+  let header: PathBuf = dir.join("qoi.h");
+  assert!(header.exists(), "no qoi.h in {}", dir.display());
+  println!("cargo:rerun-if-changed={}", header.display());
+
+  // qoi.h is header only, its implementation is compiled from a generated source.
+  let source: PathBuf = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("qoi.c");
+  std::fs::write(&source, "#define QOI_IMPLEMENTATION\n#define QOI_NO_STDIO\n#include \"qoi.h\"\n").unwrap();
+  cc::Build::new().file(&source).include(&dir).compile("qoi_reference");
+}
@@ -0,0 +1,275 @@
+/*
+Codec comparison between Qross, the image crate's QOI codec, PNG and lossless WebP: encode and decode speed, and bytes per pixel.
+Runs on a reproducible synthetic corpus, shared with 'benches/corpus.rs', or on the user's own images.
+*/
+
+use std::fmt;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use image::{ColorType, DynamicImage, ImageDecoder, ImageEncoder, RgbImage};
+use image::codecs::png::{CompressionType, FilterType, PngDecoder, PngEncoder};
+use image::codecs::qoi::{QoiDecoder, QoiEncoder};
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::comp::{decode_bytes, encode_image};
+use crate::colorspace::ColorSpace;
+use crate::qoi_errror::QoiError;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    Qross,
+    ImageQoi,
+    // PNG at the default level.
+    Png,
+    PngFast,
+    PngBest,
+    WebpLossless,
+}
+
+impl Codec {
+
+    // QOI implementations against the default PNG, what the benchmarks measure.
+    pub const ALL: [Codec; 3] = [Codec::Qross, Codec::ImageQoi, Codec::Png];
+
+    // Every lossless format a user could pick instead of QOI, for the format comparison.
+    pub const FORMATS: [Codec; 5] = [Codec::Qross, Codec::PngFast, Codec::Png, Codec::PngBest, Codec::WebpLossless];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Qross => "qross",
+            Codec::ImageQoi => "image-qoi",
+            Codec::Png => "png",
+            Codec::PngFast => "png-fast",
+            Codec::PngBest => "png-best",
+            Codec::WebpLossless => "webp-lossless",
+        }
+    }
+
+    pub fn encode(&self, img: &RgbImage) -> Result<Vec<u8>, QoiError> {
+        let (width, height) = img.dimensions();
+        let mut out: Vec<u8> = Vec::new();
+        match self {
+            Codec::Qross => out = encode_image(img.as_raw(), 3, width, height, ColorSpace::Srgb),
+            Codec::ImageQoi => QoiEncoder::new(&mut out).write_image(img.as_raw(), width, height, ColorType::Rgb8)?,
+            Codec::Png => PngEncoder::new(&mut out).write_image(img.as_raw(), width, height, ColorType::Rgb8)?,
+            Codec::PngFast => PngEncoder::new_with_quality(&mut out, CompressionType::Fast, FilterType::Adaptive)
+                .write_image(img.as_raw(), width, height, ColorType::Rgb8)?,
+            Codec::PngBest => PngEncoder::new_with_quality(&mut out, CompressionType::Best, FilterType::Adaptive)
+                .write_image(img.as_raw(), width, height, ColorType::Rgb8)?,
+            Codec::WebpLossless => WebPEncoder::new_lossless(&mut out).encode(img.as_raw(), width, height, ColorType::Rgb8)?,
+        }
+        Ok(out)
+    }
+
+    // Decodes what 'encode' wrote back into RGB pixels.
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, QoiError> {
+        match self {
+            Codec::Qross => Ok(decode_bytes(bytes, PathBuf::new())?.pixels),
+            Codec::ImageQoi => read_pixels(QoiDecoder::new(Cursor::new(bytes))?),
+            Codec::Png | Codec::PngFast | Codec::PngBest => read_pixels(PngDecoder::new(Cursor::new(bytes))?),
+            Codec::WebpLossless => read_pixels(WebPDecoder::new(Cursor::new(bytes))?),
+        }
+    }
+}
+
+// Lossless WebP always decodes to RGBA, so everything is brought back to RGB.
+fn read_pixels<'a, D: ImageDecoder<'a>>(decoder: D) -> Result<Vec<u8>, QoiError> {
+    Ok(DynamicImage::from_decoder(decoder)?.into_rgb8().into_raw())
+}
+
+// Xorshift, so the corpus is the same on every run and machine.
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as u8
+    }
+}
+
+/*
+Synthetic images standing for the usual inputs: a noisy photo, a screenshot, an icon and a smooth gradient.
+ */
+pub fn corpus() -> Vec<(&'static str, RgbImage)> {
+    let mut noise: Noise = Noise(0x9E3779B9);
+    let photo: RgbImage = RgbImage::from_fn(1024, 768, |x, y| {
+        // Slow shading with sensor-like noise on top.
+        let shade: u32 = (x * 3 + y * 2) / 11 + ((x / 40 + y / 30) % 7) * 9;
+        let n: u8 = noise.next() & 0x0F;
+        image::Rgb([(shade as u8).wrapping_add(n), (shade / 2 + 40) as u8 ^ (n >> 1), (200 - (shade % 150)) as u8 + (n >> 2)])
+    });
+
+    let screenshot: RgbImage = RgbImage::from_fn(1024, 768, |x, y| {
+        let in_window: bool = (100..700).contains(&x) && (80..600).contains(&y);
+        let text_line: bool = in_window && y % 18 < 10 && (x / 6 + y) % 7 < 4 && x % 6 < 4;
+        match (in_window, text_line, y < 32) {
+            (_, _, true) => image::Rgb([32, 34, 40]),
+            (true, true, _) => image::Rgb([20, 20, 20]),
+            (true, false, _) => image::Rgb([250, 250, 250]),
+            _ => image::Rgb([58, 110, 165]),
+        }
+    });
+
+    let icon: RgbImage = RgbImage::from_fn(64, 64, |x, y| {
+        let (dx, dy) = (x as i32 - 32, y as i32 - 32);
+        let distance: i32 = dx * dx + dy * dy;
+        match distance {
+            d if d < 24 * 24 => image::Rgb([230, (120 + y * 2) as u8, 40]),
+            d if d < 28 * 28 => image::Rgb([120, 60, 20]),
+            _ => image::Rgb([255, 255, 255]),
+        }
+    });
+
+    let gradient: RgbImage = RgbImage::from_fn(1024, 768, |x, y| {
+        image::Rgb([(x / 4) as u8, (y / 3) as u8, ((x + y) / 7) as u8])
+    });
+
+    vec![("photo", photo), ("screenshot", screenshot), ("icon", icon), ("gradient", gradient)]
+}
+
+// Best encode and decode time of one codec on one image.
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub image: String,
+    pub codec: Codec,
+    pub pixels: usize,
+    pub encoded_size: usize,
+    pub encode_time: Duration,
+    pub decode_time: Duration,
+}
+
+impl Measurement {
+    pub fn bytes_per_pixel(&self) -> f64 {
+        self.encoded_size as f64 / self.pixels.max(1) as f64
+    }
+
+    pub fn encode_mbps(&self) -> f64 {
+        megabytes_per_second(self.pixels * 3, self.encode_time)
+    }
+
+    pub fn decode_mbps(&self) -> f64 {
+        megabytes_per_second(self.pixels * 3, self.decode_time)
+    }
+}
+
+// Raw RGB bytes handled per second, in MB.
+fn megabytes_per_second(raw: usize, time: Duration) -> f64 {
+    raw as f64 / 1_000_000.0 / time.as_secs_f64().max(f64::EPSILON)
+}
+
+/*
+Encodes and decodes 'img' with 'codec' 'iterations' times and keeps the fastest run of each.
+The decoded pixels are checked against the source.
+ */
+pub fn measure(image: &str, img: &RgbImage, codec: Codec, iterations: usize) -> Result<Measurement, QoiError> {
+    let mut encoded: Vec<u8> = Vec::new();
+    let mut encode_time: Duration = Duration::MAX;
+    let mut decode_time: Duration = Duration::MAX;
+
+    for _ in 0..iterations.max(1) {
+        let start: Instant = Instant::now();
+        encoded = codec.encode(img)?;
+        encode_time = encode_time.min(start.elapsed());
+
+        let start: Instant = Instant::now();
+        let decoded: Vec<u8> = codec.decode(&encoded)?;
+        decode_time = decode_time.min(start.elapsed());
+
+        if decoded != *img.as_raw() {
+            return Err(QoiError::ChecksumMismatch(format!("{} didn't decode {} back to the source", codec.name(), image)));
+        }
+    }
+
+    Ok(Measurement {
+        image: image.to_string(),
+        codec,
+        pixels: img.width() as usize * img.height() as usize,
+        encoded_size: encoded.len(),
+        encode_time,
+        decode_time,
+    })
+}
+
+/*
+Sums the measurements of 'codec': sizes and times add up, so speeds are weighted by image size.
+ */
+pub fn total(measurements: &[Measurement], codec: Codec) -> Measurement {
+    measurements
+        .iter()
+        .filter(|m| m.codec == codec)
+        .fold(
+            Measurement {
+                image: "total".to_string(),
+                codec,
+                pixels: 0,
+                encoded_size: 0,
+                encode_time: Duration::ZERO,
+                decode_time: Duration::ZERO,
+            },
+            |mut sum, m| {
+                sum.pixels += m.pixels;
+                sum.encoded_size += m.encoded_size;
+                sum.encode_time += m.encode_time;
+                sum.decode_time += m.decode_time;
+                sum
+            },
+        )
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{:<24} {:<10} {:>8.3} B/px {:>9.1} MB/s encode {:>9.1} MB/s decode",
+            self.image, self.codec.name(), self.bytes_per_pixel(), self.encode_mbps(), self.decode_mbps()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::fixtures::sample;
+
+    fn sample_image() -> RgbImage {
+        RgbImage::from_raw(64, 64, sample(64, 64, 3)).unwrap()
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        let img: RgbImage = sample_image();
+        for codec in [Codec::ImageQoi].iter().chain(Codec::FORMATS.iter()) {
+            let encoded: Vec<u8> = codec.encode(&img).unwrap();
+            assert_eq!(codec.decode(&encoded).unwrap(), *img.as_raw(), "{}", codec.name());
+        }
+    }
+
+    #[test]
+    fn measurements_add_up() {
+        let img: RgbImage = sample_image();
+        let measurements: Vec<Measurement> = Codec::ALL
+            .iter()
+            .chain(Codec::ALL.iter())
+            .map(|codec| measure("sample", &img, *codec, 1).unwrap())
+            .collect();
+
+        let qross: Measurement = total(&measurements, Codec::Qross);
+        assert_eq!(qross.pixels, 2 * 64 * 64);
+        assert_eq!(qross.encoded_size, 2 * Codec::Qross.encode(&img).unwrap().len());
+        assert_eq!(qross.bytes_per_pixel(), measurements[0].bytes_per_pixel());
+        assert_eq!(total(&measurements, Codec::WebpLossless).pixels, 0);
+    }
+
+    #[test]
+    fn corpus_is_reproducible_and_garbage_fails() {
+        let (first, second) = (corpus(), corpus());
+        assert!(first.iter().zip(&second).all(|((_, a), (_, b))| a == b));
+
+        for codec in [Codec::Qross, Codec::Png, Codec::WebpLossless] {
+            assert!(codec.decode(b"not an image").is_err(), "{}", codec.name());
+        }
+    }
+}
@@ -0,0 +1,330 @@
+/*
+Command line interface, used instead of the GUI when Qross is started with arguments.
+*/
+
+use std::slice::Iter;
+use std::str::FromStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::{DynamicImage, ImageFormat, RgbImage};
+
+use crate::comp::{FileError, Package};
+use crate::report::CompressionReport;
+use crate::colorspace::ColorSpace;
+use crate::metrics;
+use crate::options::CompressOptions;
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+use crate::quantize::{LossyOptions, QuantizeMethod};
+use crate::tiles::TiledFile;
+use crate::sequence::Sequence;
+use crate::depth::join_planes;
+use crate::stats::EncodingStats;
+use crate::inspect::{self, InspectOptions};
+use crate::salvage::{parse_color, Salvaged, DEFAULT_FILL};
+use crate::bench::{self, corpus, Codec, Measurement};
+use crate::compare::Comparison;
+
+const USAGE: &str = "usage:
+    compress <files..> [--lossy <bitdepth|ordered|floyd-steinberg|palette> <quality>] [--parallel] [--colorspace <srgb|linear>] [--bake-orientation] [--container] [--entropy <zstd|lz4>] [--tiles <size>] [--frame-diff] [--depth <truncate|round|dither>] [--split-planes]
+        [--transform <resize|fit|fill=WxH[:filter] | crop=X,Y,WxH | rotate=90|180|270 | flip=h|v | strip-alpha>..] [--preset <options.json>]
+        [--channels <auto|rgb|gray>] [--output-dir <dir>] [--naming <template with {name}>] [--skip-verify] [--optimize (with --container)] [--stats]
+        [--auto-format <smallest|fastest-decode[=<percent over the smallest>]>]
+    metrics <source image> <qoi file>
+    stats <qoi files..>
+    salvage <damaged qoi file> <output qoi or image> [--fill <r>,<g>,<b>]
+    bench [<images or folders>..] [--iterations <n>]
+    compare <images or folders..> [--iterations <n>]
+    inspect <qoi file> [--from <pixel>] [--to <pixel>] [--index] [--non-canonical]
+    export <qoi file or sequence> <output image> [--colorspace <srgb|linear>]
+    join <high plane qoi> <low plane qoi> <output image>
+    region <tiled file> <x> <y> <width> <height> <output image> [--colorspace <srgb|linear>]";
+
+/*
+Runs the sub command found in 'args' and returns the process exit code.
+ */
+pub fn run(args: Vec<String>) -> i32 {
+    let result: Result<(), QoiError> = match args.first().map(String::as_str) {
+        Some("compress") => compress(&args[1..]),
+        Some("metrics") => metrics(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("salvage") => salvage(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("compare") => compare(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("region") => region(&args[1..]),
+        Some("join") => join(&args[1..]),
+        _ => Err(QoiError::InvalidArgument(USAGE.to_string())),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn next_value<'a>(iter: &mut Iter<'a, String>, flag: &str) -> Result<&'a String, QoiError> {
+    iter.next()
+        .ok_or_else(|| QoiError::InvalidArgument(format!("missing value for '{}'", flag)))
+}
+
+fn parse_value<T: FromStr>(value: &str) -> Result<T, QoiError> {
+    value
+        .parse()
+        .map_err(|_| QoiError::InvalidArgument(format!("invalid value '{}'", value)))
+}
+
+// Splits 'args' into positional arguments and the CompressOptions given by flags.
+fn parse_options(args: &[String]) -> Result<(Vec<String>, CompressOptions), QoiError> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut options: CompressOptions = CompressOptions::default();
+
+    let mut iter: Iter<String> = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            // Flags after the preset override it.
+            "--preset" => options = CompressOptions::load(Path::new(next_value(&mut iter, arg)?))?,
+            "--channels" => options.channel_mode = next_value(&mut iter, arg)?.parse()?,
+            "--output-dir" => options.output_dir = Some(next_value(&mut iter, arg)?.clone()),
+            "--naming" => options.naming = Some(next_value(&mut iter, arg)?.clone()),
+            "--skip-verify" => options.skip_verify = true,
+            "--optimize" => options.optimize = true,
+            "--stats" => options.stats = true,
+            "--transform" => options.transforms.push(next_value(&mut iter, arg)?.parse()?),
+            "--lossy" => {
+                let method: QuantizeMethod = next_value(&mut iter, arg)?.parse()?;
+                let quality: u8 = parse_value(next_value(&mut iter, arg)?)?;
+                options.lossy = Some(LossyOptions { method, quality });
+            }
+            "--parallel" => options.parallel = true,
+            "--container" => options.container = true,
+            "--frame-diff" => options.frame_diff = true,
+            "--depth" => options.depth = next_value(&mut iter, arg)?.parse()?,
+            "--split-planes" => options.split_planes = true,
+            "--tiles" => options.tile_size = Some(parse_value(next_value(&mut iter, arg)?)?),
+            "--auto-format" => options.auto_format = Some(next_value(&mut iter, arg)?.parse()?),
+            "--entropy" => options.entropy = Some(next_value(&mut iter, arg)?.parse()?),
+            "--colorspace" => options.colorspace = Some(next_value(&mut iter, arg)?.parse()?),
+            "--bake-orientation" => options.bake_orientation = true,
+            flag if flag.starts_with("--") => {
+                return Err(QoiError::InvalidArgument(format!("unknown flag '{}'", flag)));
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    Ok((positional, options))
+}
+
+fn compress(args: &[String]) -> Result<(), QoiError> {
+    let (files, options) = parse_options(args)?;
+    if files.is_empty() {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    }
+
+    // Every file is tried, the failures are printed as they come and fail the command at the end.
+    let pack: Package = Package::with_options(files, options);
+    let results: Vec<Result<CompressionReport, FileError>> = pack.compress_all();
+    let total: usize = results.len();
+    let mut failed: usize = 0;
+    for result in results {
+        match result {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(QoiError::SavingError(format!("{} of {} files failed", failed, total))),
+    }
+}
+
+fn metrics(args: &[String]) -> Result<(), QoiError> {
+    let [source, output] = args else {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    };
+
+    let source: DynamicImage = image::open(Path::new(source))?;
+    let output: QoiFile = QoiFile::open(Path::new(output))?;
+    println!("{}", metrics::compare(&source, &output)?);
+    Ok(())
+}
+
+fn stats(args: &[String]) -> Result<(), QoiError> {
+    if args.is_empty() {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    }
+
+    for path in args {
+        let stats: EncodingStats = EncodingStats::from_stream(&std::fs::read(path)?)?;
+        println!("{}\n{}", path, stats);
+    }
+    Ok(())
+}
+
+fn inspect(args: &[String]) -> Result<(), QoiError> {
+    let mut options: InspectOptions = InspectOptions::default();
+    let mut file: Option<&String> = None;
+
+    let mut iter: Iter<String> = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--from" => options.from = parse_value(next_value(&mut iter, arg)?)?,
+            "--to" => options.to = Some(parse_value(next_value(&mut iter, arg)?)?),
+            "--index" => options.index_table = true,
+            "--non-canonical" => options.non_canonical_only = true,
+            flag if flag.starts_with("--") => {
+                return Err(QoiError::InvalidArgument(format!("unknown flag '{}'", flag)));
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(QoiError::InvalidArgument(USAGE.to_string())),
+        }
+    }
+
+    let file: &String = file.ok_or_else(|| QoiError::InvalidArgument(USAGE.to_string()))?;
+    println!("{}", inspect::inspect(&std::fs::read(file)?, &options)?);
+    Ok(())
+}
+
+fn salvage(args: &[String]) -> Result<(), QoiError> {
+    let (paths, fill) = match args {
+        [input, output, flag, color] if flag == "--fill" => ([input, output], parse_color(color)?),
+        [input, output] => ([input, output], DEFAULT_FILL),
+        _ => return Err(QoiError::InvalidArgument(USAGE.to_string())),
+    };
+    let [input, output] = paths;
+
+    let salvaged: Salvaged = Salvaged::open(Path::new(input), fill)?;
+    println!("{}", salvaged);
+    // A QOI output is re-encoded as a valid file, anything else is exported.
+    if output.to_lowercase().ends_with(".qoi") {
+        salvaged.save(Path::new(output))?;
+    } else {
+        salvaged.file.export(Path::new(output), ColorSpace::Srgb)?;
+    }
+    Ok(())
+}
+
+// Images given on the command line, folders are searched one level deep for formats the image crate reads.
+fn image_paths(args: &[String]) -> Result<Vec<PathBuf>, QoiError> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for arg in args {
+        let path: &Path = Path::new(arg);
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && ImageFormat::from_path(p).is_ok())
+                .collect();
+            entries.sort();
+            paths.extend(entries);
+        } else {
+            paths.push(path.to_path_buf());
+        }
+    }
+    Ok(paths)
+}
+
+// Splits 'args' into inputs and the '--iterations' value, 5 by default.
+fn parse_iterations(args: &[String]) -> Result<(Vec<String>, usize), QoiError> {
+    let mut iterations: usize = 5;
+    let mut inputs: Vec<String> = Vec::new();
+
+    let mut iter: Iter<String> = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--iterations" => iterations = parse_value(next_value(&mut iter, arg)?)?,
+            flag if flag.starts_with("--") => {
+                return Err(QoiError::InvalidArgument(format!("unknown flag '{}'", flag)));
+            }
+            _ => inputs.push(arg.clone()),
+        }
+    }
+    Ok((inputs, iterations))
+}
+
+fn bench(args: &[String]) -> Result<(), QoiError> {
+    let (inputs, iterations) = parse_iterations(args)?;
+
+    // Without inputs the synthetic corpus is measured.
+    let images: Vec<(String, RgbImage)> = if inputs.is_empty() {
+        corpus().into_iter().map(|(name, img)| (name.to_string(), img)).collect()
+    } else {
+        image_paths(&inputs)?
+            .iter()
+            .map(|path| Ok((path.display().to_string(), image::open(path)?.to_rgb8())))
+            .collect::<Result<Vec<(String, RgbImage)>, QoiError>>()?
+    };
+
+    let mut measurements: Vec<Measurement> = Vec::new();
+    for (name, img) in &images {
+        for codec in Codec::ALL {
+            let measurement: Measurement = bench::measure(name, img, codec, iterations)?;
+            println!("{}", measurement);
+            measurements.push(measurement);
+        }
+    }
+    for codec in Codec::ALL {
+        println!("{}", bench::total(&measurements, codec));
+    }
+    Ok(())
+}
+
+fn compare(args: &[String]) -> Result<(), QoiError> {
+    let (inputs, iterations) = parse_iterations(args)?;
+    if inputs.is_empty() {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    }
+
+    for path in image_paths(&inputs)? {
+        println!("{}", Comparison::run(&path, iterations)?);
+    }
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<(), QoiError> {
+    let (paths, options) = parse_options(args)?;
+    let [input, output] = paths.as_slice() else {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    };
+
+    let color_space: ColorSpace = options.colorspace.unwrap_or(ColorSpace::Srgb);
+    if Sequence::is_sequence(Path::new(input)) {
+        let sequence: Sequence = Sequence::open(Path::new(input))?;
+        println!("{} holds {} frames", input, sequence.frames.len());
+        return sequence.export(Path::new(output), color_space);
+    }
+
+    let qoi_file: QoiFile = QoiFile::open(Path::new(input))?;
+    println!("{} is {:?}", input, qoi_file.colorspace());
+    qoi_file.export(Path::new(output), color_space)
+}
+
+fn region(args: &[String]) -> Result<(), QoiError> {
+    let (paths, options) = parse_options(args)?;
+    let [input, x, y, width, height, output] = paths.as_slice() else {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    };
+
+    let tiled: TiledFile = TiledFile::open(Path::new(input))?;
+    let region: QoiFile = tiled.read_region(parse_value(x)?, parse_value(y)?, parse_value(width)?, parse_value(height)?)?;
+    region.export(Path::new(output), options.colorspace.unwrap_or(ColorSpace::Srgb))
+}
+
+fn join(args: &[String]) -> Result<(), QoiError> {
+    let [high, low, output] = args else {
+        return Err(QoiError::InvalidArgument(USAGE.to_string()));
+    };
+
+    let high: QoiFile = QoiFile::open(Path::new(high))?;
+    let low: QoiFile = QoiFile::open(Path::new(low))?;
+    join_planes(&high, &low)?.save(Path::new(output))?;
+    Ok(())
+}
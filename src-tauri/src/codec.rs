@@ -0,0 +1,196 @@
+/*
+The Qross codec behind the `image` crate's ImageEncoder and ImageDecoder traits,
+so anything written against `image` can read and write QOI through our implementation.
+*/
+
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use image::{ColorType, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageResult};
+use image::error::{ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+
+use crate::comp::{decode_bytes, encode_image};
+use crate::colorspace::ColorSpace;
+use crate::container::Trailer;
+use crate::entropy::{Entropy, EntropyWriter};
+use crate::options::CompressOptions;
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+use crate::stripes::{encode_striped, stripe_rows};
+
+/*
+Writes a QOI stream into 'writer'. Only the stream options apply: the header colorspace,
+striped parallel encoding, the container trailer and the second compression stage.
+Pixels are written as given, like the rest of the encoder alpha is dropped.
+ */
+pub struct QrossEncoder<W: Write> {
+    writer: W,
+    color_space: ColorSpace,
+    parallel: bool,
+    container: bool,
+    entropy: Option<Entropy>,
+}
+
+impl<W: Write> QrossEncoder<W> {
+
+    // Plain sRGB QOI.
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, &CompressOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: &CompressOptions) -> Self {
+        Self {
+            writer,
+            color_space: options.colorspace.unwrap_or(ColorSpace::Srgb),
+            parallel: options.parallel,
+            container: options.container,
+            entropy: options.entropy,
+        }
+    }
+
+    /*
+    Encodes 'pixels' ('channels' bytes per pixel), returns the number of written bytes.
+     */
+    pub fn encode(self, pixels: &[u8], channels: usize, width: u32, height: u32) -> Result<usize, QoiError> {
+        if pixels.len() != width as usize * height as usize * channels {
+            return Err(QoiError::DimensionMismatch(format!(
+                "{} bytes for a {}x{} image with {} channels", pixels.len(), width, height, channels
+            )));
+        }
+
+        let mut buf_writer: EntropyWriter<W> = EntropyWriter::new(self.writer, self.entropy)?;
+        let mut bytes: usize = if self.parallel {
+            encode_striped(pixels, channels, width, height, self.color_space, stripe_rows(height), &mut buf_writer)?
+        } else {
+            let out: Vec<u8> = encode_image(pixels, channels, width, height, self.color_space);
+            buf_writer.write_all(&out)?;
+            out.len()
+        };
+        if self.container {
+            bytes += Trailer::new(pixels, channels, Path::new("")).write(&mut buf_writer)?;
+        }
+        buf_writer.finish()?.flush()?;
+        Ok(bytes)
+    }
+}
+
+impl<W: Write> ImageEncoder for QrossEncoder<W> {
+
+    // 8 bit gray and color layouts are encoded directly, anything else is unsupported.
+    fn write_image(self, buf: &[u8], width: u32, height: u32, color_type: ColorType) -> ImageResult<()> {
+        match color_type {
+            ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => {
+                self.encode(buf, color_type.channel_count() as usize, width, height)?;
+                Ok(())
+            }
+            _ => Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                ImageFormatHint::Exact(ImageFormat::Qoi),
+                UnsupportedErrorKind::Color(color_type.into()),
+            ))),
+        }
+    }
+}
+
+/*
+Decodes a whole QOI stream up front, compressed streams and trailers are handled like in 'decode_bytes'.
+ */
+pub struct QrossDecoder {
+    file: QoiFile,
+}
+
+impl QrossDecoder {
+
+    pub fn new<R: Read>(mut reader: R) -> Result<Self, QoiError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let mut file: QoiFile = decode_bytes(&bytes, PathBuf::new())?;
+        file.set_size();
+        Ok(Self { file })
+    }
+
+    // Colorspace found in the header.
+    pub fn colorspace(&self) -> ColorSpace {
+        self.file.colorspace()
+    }
+
+    pub fn into_file(self) -> QoiFile {
+        self.file
+    }
+}
+
+impl<'a> ImageDecoder<'a> for QrossDecoder {
+    type Reader = Cursor<Vec<u8>>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.file.width, self.file.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        if self.file.channels == 4 { ColorType::Rgba8 } else { ColorType::Rgb8 }
+    }
+
+    fn into_reader(self) -> ImageResult<Self::Reader> {
+        Ok(Cursor::new(self.file.pixels))
+    }
+
+    // The pixels are already decoded, they are only copied.
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        buf.copy_from_slice(&self.file.pixels);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    fn sample() -> Vec<u8> {
+        (0..32 * 24 * 3).map(|i| (i / 3 % 32 * 8 + i % 3 * 40) as u8).collect()
+    }
+
+    #[test]
+    fn image_traits_round_trip_with_stream_options() {
+        let pixels: Vec<u8> = sample();
+        let options: CompressOptions = CompressOptions {
+            colorspace: Some(ColorSpace::Linear),
+            parallel: true,
+            container: true,
+            entropy: Some(Entropy::Zstd),
+            ..Default::default()
+        };
+
+        for options in [CompressOptions::default(), options] {
+            let mut bytes: Vec<u8> = Vec::new();
+            QrossEncoder::with_options(&mut bytes, &options).write_image(&pixels, 32, 24, ColorType::Rgb8).unwrap();
+
+            let decoder: QrossDecoder = QrossDecoder::new(bytes.as_slice()).unwrap();
+            assert_eq!(decoder.colorspace(), options.colorspace.unwrap_or(ColorSpace::Srgb));
+            assert_eq!(decoder.color_type(), ColorType::Rgb8);
+            assert_eq!(DynamicImage::from_decoder(decoder).unwrap().into_rgb8().into_raw(), pixels);
+        }
+    }
+
+    #[test]
+    fn gray_layouts_decode_as_rgb() {
+        let gray: Vec<u8> = (0..16).map(|v| v * 16).collect();
+        let mut bytes: Vec<u8> = Vec::new();
+        QrossEncoder::new(&mut bytes).write_image(&gray, 4, 4, ColorType::L8).unwrap();
+
+        let file: QoiFile = QrossDecoder::new(bytes.as_slice()).unwrap().into_file();
+        assert_eq!(file.pixels, gray.iter().flat_map(|v| [*v; 3]).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn unsupported_layouts_and_bad_input_are_rejected() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let wide: Result<(), ImageError> = QrossEncoder::new(&mut bytes).write_image(&[0; 12], 1, 1, ColorType::Rgb16);
+        assert!(matches!(wide, Err(ImageError::Unsupported(_))));
+
+        let short: Result<usize, QoiError> = QrossEncoder::new(&mut bytes).encode(&[0; 11], 3, 2, 2);
+        assert!(matches!(short, Err(QoiError::DimensionMismatch(_))));
+        assert!(bytes.is_empty());
+
+        assert!(matches!(QrossDecoder::new(&b"not a qoi file"[..]), Err(QoiError::InvalidHeader(_))));
+    }
+}
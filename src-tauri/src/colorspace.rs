@@ -0,0 +1,165 @@
+/*
+QOI header colorspace, and conversions between sRGB and linear 8 bit values.
+Alpha is always linear and never converted.
+*/
+
+use std::str::FromStr;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::consts::{QOI_LINEAR, QOI_SRGB};
+use crate::qoi_errror::QoiError;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    // sRGB with linear alpha.
+    Srgb,
+    // All channels linear.
+    Linear,
+}
+
+impl ColorSpace {
+
+    pub fn from_header(byte: u8) -> Self {
+        if byte == QOI_LINEAR { ColorSpace::Linear } else { ColorSpace::Srgb }
+    }
+
+    pub fn header_byte(&self) -> u8 {
+        match self {
+            ColorSpace::Srgb => QOI_SRGB,
+            ColorSpace::Linear => QOI_LINEAR,
+        }
+    }
+
+    /*
+    Colorspace of the 8 bit pixels Qross reads out of 'img'.
+    Float images (EXR, HDR) hold linear light, every other layout comes from sRGB files.
+     */
+    pub fn of_image(img: &DynamicImage) -> Self {
+        match img {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear" => Ok(ColorSpace::Linear),
+            _ => Err(QoiError::InvalidArgument(format!("unknown colorspace '{}'", s))),
+        }
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Lookup table converting 8 bit values into 'to'.
+fn table(to: ColorSpace) -> [u8; 256] {
+    let convert: fn(f32) -> f32 = match to {
+        ColorSpace::Linear => srgb_to_linear,
+        ColorSpace::Srgb => linear_to_srgb,
+    };
+
+    let mut lut: [u8; 256] = [0; 256];
+    for (i, v) in lut.iter_mut().enumerate() {
+        *v = (convert(i as f32 / 255.0) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+/*
+Converts the color channels of 'pixels' ('channels' bytes per pixel) from 'from' to 'to' in place.
+ */
+pub fn convert(pixels: &mut [u8], channels: usize, from: ColorSpace, to: ColorSpace) {
+    if from == to {
+        return;
+    }
+
+    // Gray layouts hold a single color channel.
+    let color_channels: usize = if channels < 3 { 1 } else { 3 };
+    let lut: [u8; 256] = table(to);
+
+    for px in pixels.chunks_exact_mut(channels) {
+        px[..color_channels].iter_mut().for_each(|v| *v = lut[*v as usize]);
+    }
+}
+
+/*
+Counts the color samples of 'pixels' that don't come back unchanged from 'from' to 'to' and back at 8 bits.
+ */
+pub fn conversion_loss(pixels: &[u8], channels: usize, from: ColorSpace, to: ColorSpace) -> usize {
+    if from == to {
+        return 0;
+    }
+
+    let color_channels: usize = if channels < 3 { 1 } else { 3 };
+    let (forward, back): ([u8; 256], [u8; 256]) = (table(to), table(from));
+
+    pixels
+        .chunks_exact(channels)
+        .flat_map(|px| &px[..color_channels])
+        .filter(|v| back[forward[**v as usize] as usize] != **v)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion_loss_counts_dark_srgb_values_lost_in_linear() {
+        let every_value: Vec<u8> = (0..=255).collect();
+        assert_eq!(conversion_loss(&every_value, 1, ColorSpace::Srgb, ColorSpace::Srgb), 0);
+        assert!(conversion_loss(&every_value, 1, ColorSpace::Srgb, ColorSpace::Linear) > 0);
+
+        // Black and white survive, alpha is never counted.
+        assert_eq!(conversion_loss(&[0, 0, 0, 7, 255, 255, 255, 9], 4, ColorSpace::Srgb, ColorSpace::Linear), 0);
+    }
+
+    #[test]
+    fn round_trip_loses_exactly_what_conversion_loss_counts() {
+        let every_value: Vec<u8> = (0..=255).collect();
+        for (from, to) in [(ColorSpace::Srgb, ColorSpace::Linear), (ColorSpace::Linear, ColorSpace::Srgb)] {
+            let mut pixels: Vec<u8> = every_value.clone();
+            convert(&mut pixels, 1, from, to);
+            convert(&mut pixels, 1, to, from);
+
+            let changed: usize = pixels.iter().zip(&every_value).filter(|(a, b)| a != b).count();
+            assert_eq!(changed, conversion_loss(&every_value, 1, from, to), "{:?} to {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn alpha_is_never_converted() {
+        for channels in [2, 4] {
+            let pixels: Vec<u8> = (0..=255).flat_map(|v| [v; 4][..channels].to_vec()).collect();
+            let mut converted: Vec<u8> = pixels.clone();
+            convert(&mut converted, channels, ColorSpace::Srgb, ColorSpace::Linear);
+
+            let alpha = |pixels: &[u8]| pixels.chunks_exact(channels).map(|px| px[channels - 1]).collect::<Vec<u8>>();
+            assert_eq!(alpha(&converted), alpha(&pixels), "{} channels", channels);
+            assert_ne!(converted, pixels);
+        }
+    }
+
+    #[test]
+    fn float_images_are_linear() {
+        assert_eq!(ColorSpace::of_image(&DynamicImage::new_rgb32f(2, 2)), ColorSpace::Linear);
+        assert_eq!(ColorSpace::of_image(&DynamicImage::new_rgba32f(2, 2)), ColorSpace::Linear);
+        assert_eq!(ColorSpace::of_image(&DynamicImage::new_rgb8(2, 2)), ColorSpace::Srgb);
+        assert_eq!(ColorSpace::of_image(&DynamicImage::new_rgba16(2, 2)), ColorSpace::Srgb);
+
+        assert_eq!(ColorSpace::from_header(ColorSpace::Linear.header_byte()), ColorSpace::Linear);
+        assert!(matches!("adobe".parse::<ColorSpace>(), Err(QoiError::InvalidArgument(_))));
+    }
+}
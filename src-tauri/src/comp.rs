@@ -6,42 +6,60 @@ use crate::consts::*;
 use crate::qoi_file::QoiFile;
 use crate::pixel::{Pixel, Zero};
 use crate::qoi_errror::QoiError;
+use crate::options::CompressOptions;
+use crate::report::CompressionReport;
+use crate::metrics::{self, QualityReport};
+use crate::quantize::quantize;
+use crate::stripes::{encode_striped, stripe_rows};
+use crate::tiles::{encode_tiled, TiledFile};
+use crate::sequence::Sequence;
+use crate::depth::{self, bit_depth, is_high_depth, split_planes};
+use crate::transform::apply_all;
+use crate::optimize::{optimize, ColorTransform, Optimized};
+use crate::colorspace::{conversion_loss, convert, ColorSpace};
+use crate::metadata::Metadata;
+use crate::container::Trailer;
+use crate::stats::EncodingStats;
+use crate::format::{choose, FormatChoice, OutputFormat};
+use crate::entropy::{self, Entropy, EntropyWriter};
+use crate::simd::{hash_block, run_length, HASH_BLOCK};
 
 extern crate rayon;
 use itertools::Itertools;
 use rayon::prelude::*;
 
 use image::DynamicImage;
+use std::borrow::Cow;
+use std::fmt;
 use std::fs::{File, self};
 use std::path::{Path, PathBuf};
-use std::io::{BufWriter, Write, BufReader, Read, Seek, Error};
+use std::io::{BufWriter, Write, BufReader, Read, Error, ErrorKind};
 
-// Read N bytes from 'reader'.
-fn read_from_buffer<const N: usize>(reader: &mut BufReader<File>, read_bytes: &mut usize) -> Result<[u8; N], QoiError> { 
-    let mut bytes: [u8; N] = [u8::MIN; N];
-    reader.read_exact(&mut bytes)?;
-
-    *read_bytes += N;
-    Ok(bytes)
-}
-
-// Modified read_from_buffer to read one bytes.
-fn read_u8(reader: &mut BufReader<File>, read_bytes: &mut usize) -> Result<[u8; 1], QoiError> {
-    read_from_buffer::<1>(reader, read_bytes)
+pub struct Data {
+    pub path: String, 
+    pub img: DynamicImage,
+    pub options: CompressOptions,
+    pub metadata: Metadata,
+    // Every frame of an animated source, 'img' only holds the first one.
+    pub sequence: Option<Sequence>,
 }
 
-// Modified read_from_buffer to read 4 bytes.
-fn read_u32(reader: &mut BufReader<File>, read_bytes: &mut usize) -> Result<[u8; 4], QoiError> {
-    read_from_buffer::<4>(reader, read_bytes)
+pub struct Package {
+    // Every file given, or why it couldn't be opened.
+    pub collection: Vec<Result<Data, FileError>>
 }
 
-pub struct Data {
-    pub path: String, 
-    pub img: DynamicImage
+// A file of the Package that couldn't be opened or compressed.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: String,
+    pub error: QoiError,
 }
 
-pub struct Package {
-    pub collection: Vec<Data>
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
 }
 
 impl Package {
@@ -50,27 +68,33 @@ impl Package {
     Returns a Package built from 'files'. 
      */
     pub fn with_files(files: Vec<String>) -> Self {
+        Self::with_options(files, CompressOptions::default())
+    }
+
+    /*
+    Returns a Package built from 'files', every Data is compressed with 'options'.
+    Files that can't be opened stay in the collection as errors.
+     */
+    pub fn with_options(files: Vec<String>, options: CompressOptions) -> Self {
         Self { collection: 
             files
             .iter()
-            .map(|p: &String| { 
-                Data {
-                    path: p.to_string(),
-                    img: image::open(Path::new(p)).unwrap(),
-                }
-            })
+            .map(|p: &String| Data::open(p, &options).map_err(|error| FileError { path: p.to_string(), error }))
             .collect_vec()      
         }
     }
 
-    // Compresses all files in Package.
-    pub fn compress_all(&mut self) {
-        self.collection.par_iter_mut().for_each(|d| { let _ = d.compress(); });
+    // Compresses all files in Package, returns a report or the error of every file, in order.
+    pub fn compress_all(self) -> Vec<Result<CompressionReport, FileError>> {
+        self.collection
+            .into_par_iter()
+            .map(|d| d.and_then(|data| data.compress().map_err(|error| FileError { path: data.path.clone(), error })))
+            .collect()
     }
 }
 
 pub trait QoiEncode {
-    fn encode(&self, pixels: &mut Vec<u8>, buffer: &mut BufWriter<File>) -> Result<usize, Error>;
+    fn encode<W: Write>(&self, pixels: &[u8], channels: usize, buffer: &mut W) -> Result<usize, Error>;
 }
 
 pub trait QoiDecode { 
@@ -79,310 +103,874 @@ pub trait QoiDecode {
 
 impl Data {
 
+    /*
+    Opens the image at 'path' and applies the orientation, transforms and channel layout of 'options'.
+     */
+    pub fn open(path: &str, options: &CompressOptions) -> Result<Self, QoiError> {
+        let mut img: DynamicImage = image::open(Path::new(path))?;
+        let mut metadata: Metadata = Metadata::read(Path::new(path)).unwrap_or_default();
+        if options.bake_orientation {
+            img = metadata.bake_orientation(img);
+        }
+        img = apply_all(&options.transforms, img);
+        img = options.channel_mode.apply(img);
+        metadata.grayscale = !img.color().has_color();
+
+        Ok(Data {
+            path: path.to_string(),
+            img,
+            options: options.clone(),
+            metadata,
+            sequence: Sequence::read(Path::new(path))
+                .unwrap_or(None)
+                .map(|s| s.transformed(&options.transforms, options.channel_mode)),
+        })
+    }
+
+    // RGB pixels, high bit depth sources are brought down with the depth option.
     pub fn get_pixels(&self) -> Vec<u8> {
-        self.img.to_rgb8().into_raw()
+        depth::to_rgb8(&self.img, self.options.depth)
+    }
+
+    // High bit depth source stored losslessly as two planes.
+    fn splits_planes(&self) -> bool {
+        self.options.split_planes && is_high_depth(&self.img)
+    }
+
+    /*
+    Borrows the image bytes when the encoder can read their layout directly, returns them with the amount of channels.
+    Any other layout is converted to RGB.
+     */
+    pub fn pixel_slice(&self) -> (Cow<'_, [u8]>, usize) {
+        match &self.img {
+            DynamicImage::ImageLuma8(img) => (Cow::Borrowed(img.as_raw()), 1),
+            DynamicImage::ImageLumaA8(img) => (Cow::Borrowed(img.as_raw()), 2),
+            DynamicImage::ImageRgb8(img) => (Cow::Borrowed(img.as_raw()), 3),
+            DynamicImage::ImageRgba8(img) => (Cow::Borrowed(img.as_raw()), 4),
+            _ => (Cow::Owned(self.get_pixels()), CHANNELS as usize),
+        }
+    }
+
+    // Colorspace of the encoded pixels, split planes are never converted.
+    pub fn colorspace(&self) -> ColorSpace {
+        if self.splits_planes() {
+            return ColorSpace::of_image(&self.img);
+        }
+        self.options.colorspace.unwrap_or_else(|| ColorSpace::of_image(&self.img))
     }
 
-    pub fn compress(&self) -> Result<(), QoiError> {
+    pub fn compress(&self) -> Result<CompressionReport, QoiError> {
+
+        let img_name: &str = Path::new(&self.path)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| QoiError::InvalidArgument(format!("no file name in '{}'", self.path)))?;
+
+        let folder: &Path = Path::new(self.options.output_dir.as_deref().unwrap_or(IMG_FOLDER_PATH));
+        fs::create_dir_all(folder)?;
+
+        let stem: String = self.options.file_stem(img_name);
+        let mut encoded_suffix = stem.clone() + ".qoi";
+        if self.sequence.is_some() {
+            encoded_suffix += "s";
+        } else if self.options.tile_size.is_some() {
+            encoded_suffix += "t";
+        } else if let Some(entropy) = &self.options.entropy {
+            encoded_suffix = encoded_suffix + "." + entropy.extension();
+        }
+        let decoded_suffix = stem.clone() + "_decoded.qoi";
+        let low_suffix = stem.clone() + "_lo.qoi";
 
-        let mut img_name = Path::new(&self.path)
-                .file_name()
-                .unwrap_or_else(|| panic!("Problem at reading file!")).to_str().unwrap();
-        img_name = &img_name[0..img_name.len() - 4];
-        
-        let encoded_suffix = img_name.to_owned() + "_encoded.qoi";
-        let decoded_suffix = img_name.to_owned() + "_decoded.qoi";
+        let mut encoded_path = folder.join(encoded_suffix);
+        let decoded_path = folder.join(decoded_suffix);
 
-        let encoded_path = Path::new(IMG_FOLDER_PATH).join(encoded_suffix);
-        let decoded_path = Path::new(IMG_FOLDER_PATH).join(decoded_suffix);
+        if let Some(sequence) = &self.sequence {
+            return self.compress_sequence(sequence, encoded_path);
+        }
  
-        let mut buf_writer: BufWriter<File> = BufWriter::new(File::create(&encoded_path).unwrap());
-        let bytes: usize = self.encode(&mut self.get_pixels(), &mut buf_writer)?; // encoded bytes. 
-        let encoded_size: usize = fs::metadata(&encoded_path)?.len() as usize;
+        // Bytes of the decoded source, 16 bit and float samples count in full.
+        let raw_size: usize = self.img.width() as usize * self.img.height() as usize * self.img.color().bytes_per_pixel() as usize;
+        let mut warnings: Vec<String> = Vec::new();
+
+        // High bytes are encoded as the image, low bytes as a second one.
+        let mut low_plane: Option<Vec<u8>> = None;
+        let (mut pixels, mut channels) = if self.splits_planes() {
+            let (high, low) = split_planes(&self.img);
+            low_plane = Some(low);
+            (Cow::Owned(high), CHANNELS as usize)
+        } else {
+            self.pixel_slice()
+        };
+
+        if low_plane.is_some() {
+            if bit_depth(&self.img) > 16 {
+                warnings.push("float source clamped to 16 bits before splitting".to_string());
+            }
+        }
+        else if is_high_depth(&self.img) {
+            warnings.push(format!("{} bit source reduced to 8 bits ({:?})", bit_depth(&self.img), self.options.depth));
+        }
+
+        // Lossy stage, applied to an RGB copy before the encoder.
+        if let (Some(lossy), None) = (&self.options.lossy, &low_plane) {
+            let mut quantized: Vec<u8> = self.get_pixels();
+            quantize(&mut quantized, self.img.width(), CHANNELS as usize, lossy);
+            (pixels, channels) = (Cow::Owned(quantized), CHANNELS as usize);
+        }
+
+        // Convert when a colorspace other than the source's was requested.
+        let source_space: ColorSpace = ColorSpace::of_image(&self.img);
+        if self.colorspace() != source_space {
+            let lost: usize = conversion_loss(&pixels, channels, source_space, self.colorspace());
+            if lost > 0 {
+                warnings.push(format!(
+                    "{} color samples don't survive {:?} to {:?} at 8 bits, quality is measured in {:?}",
+                    lost, source_space, self.colorspace(), source_space
+                ));
+            }
+            convert(pixels.to_mut(), channels, source_space, self.colorspace());
+        }
+
+        // Auto format tries QOI, QOI + zstd and PNG on the pixels about to be encoded, PNG is written apart.
+        let tiled: bool = self.options.tile_size.is_some();
+        let mut entropy: Option<Entropy> = if tiled { None } else { self.options.entropy };
+        if tiled {
+            // Tiles are read in place, so the file stays plain QOI tiles.
+            if let Some(stage) = self.options.entropy {
+                warnings.push(format!("{:?} second stage skipped, tiled files are read in place", stage));
+            }
+            if self.options.container {
+                warnings.push("container trailer skipped, tiled files have none".to_string());
+            }
+            if self.options.parallel {
+                warnings.push("parallel ignored, tiles are always encoded in parallel".to_string());
+            }
+        }
+        let mut format: Option<FormatChoice> = None;
+        if let Some(policy) = self.options.auto_format {
+            if tiled || low_plane.is_some() {
+                warnings.push("auto format skipped, tiled and split files are always QOI".to_string());
+            } else {
+                let choice: FormatChoice = choose(policy, &pixels, channels, self.img.width(), self.img.height(), self.colorspace())?;
+                encoded_path = folder.join(format!("{}.{}", stem, choice.chosen.extension()));
+                if choice.chosen == OutputFormat::Png {
+                    return self.compress_png(&pixels, channels, encoded_path, raw_size, choice, warnings);
+                }
+                entropy = choice.chosen.entropy();
+                format = Some(choice);
+            }
+        }
+
+        // Optimize mode keeps the color transform giving the smallest stream, the trailer records it.
+        // Without one, other decoders and this one would show the transformed colors without any error.
+        let mut color_transform: Option<ColorTransform> = None;
+        let mut optimize_saved: Option<usize> = None;
+        if self.options.optimize && low_plane.is_none() {
+            if !self.options.container || tiled {
+                warnings.push("optimize skipped, the color transform needs a container trailer and tiled files have none".to_string());
+            } else {
+                let optimized: Optimized = optimize(&pixels, channels, self.img.width(), self.img.height(), self.colorspace());
+                if !optimized.transform.is_identity() {
+                    optimized.transform.forward(pixels.to_mut(), channels);
+                    color_transform = Some(optimized.transform);
+                }
+                optimize_saved = Some(optimized.saved);
+            }
+        }
+
+        let file_writer: BufWriter<File> = BufWriter::new(File::create(&encoded_path)?);
+
+        let mut buf_writer: EntropyWriter<BufWriter<File>> = EntropyWriter::new(file_writer, entropy)?;
+        let mut bytes: usize = if let Some(tile_size) = self.options.tile_size {
+            encode_tiled(&pixels, channels, self.img.width(), self.img.height(), self.colorspace(), tile_size, &mut buf_writer)?
+        } else if self.options.parallel {
+            let rows: u32 = stripe_rows(self.img.height());
+            encode_striped(&pixels, channels, self.img.width(), self.img.height(), self.colorspace(), rows, &mut buf_writer)?
+        } else {
+            self.encode(&pixels, channels, &mut buf_writer)?
+        }; // encoded bytes. 
+        if self.options.container && !tiled {
+            let mut trailer: Trailer = Trailer::new(&pixels, channels, Path::new(&self.path));
+            trailer.color_transform = color_transform;
+            bytes += trailer.write(&mut buf_writer)?;
+        }
+        buf_writer.finish()?;
+        let mut encoded_size: usize = fs::metadata(&encoded_path)?.len() as usize;
 
-        assert_eq!(bytes, encoded_size);
+        // The second stage shrinks the file, plain QOI must match what the encoder wrote.
+        if entropy.is_none() && bytes != encoded_size {
+            return Err(QoiError::SavingError(format!("{} holds {} bytes, {} were written", encoded_path.display(), encoded_size, bytes)));
+        }
 
-        let mut buf_reader: BufReader<File> = BufReader::new(File::open(&encoded_path).unwrap());
-        let mut qoi_file: QoiFile = self.decode(&mut buf_reader, decoded_path)
-        .unwrap_or_else(|e| panic!("{}", e));
+        let low_path: PathBuf = folder.join(low_suffix);
+        if let Some(low) = &low_plane {
+            let encoded_low: Vec<u8> = encode_image(low, CHANNELS as usize, self.img.width(), self.img.height(), self.colorspace());
+            fs::write(&low_path, &encoded_low)?;
+            bytes += encoded_low.len();
+            encoded_size += encoded_low.len();
+        }
 
-        // parse the pixels to the QOI image.
-        qoi_file.set_size(); 
-        qoi_file.create(qoi_file.clone().path);
+        // Stats are read back from the written stream, a tiled file has one per tile.
+        let stats: Option<EncodingStats> = match (self.options.stats, tiled) {
+            (true, false) => Some(EncodingStats::from_stream(&fs::read(&encoded_path)?)?),
+            (true, true) => {
+                warnings.push("no encoding stats for tiled files".to_string());
+                None
+            }
+            _ => None,
+        };
 
-        Ok(())
+        // Verification decodes what was written and compares it against the source.
+        let quality: Option<QualityReport> = if self.options.skip_verify {
+            None
+        } else {
+            let mut qoi_file: QoiFile = if tiled {
+                let mut whole: QoiFile = TiledFile::open(&encoded_path)?.read_all()?;
+                whole.path = decoded_path;
+                whole
+            } else {
+                let mut buf_reader: BufReader<File> = BufReader::new(File::open(&encoded_path)?);
+                self.decode(&mut buf_reader, decoded_path)?
+            };
+
+            qoi_file.restore_colors();
+
+            // parse the pixels to the QOI image.
+            qoi_file.set_size(); 
+            qoi_file.create(&qoi_file.path)?;
+
+            // Both planes are compared when split.
+            Some(match &low_plane {
+                Some(low) => {
+                    let decoded_low: QoiFile = QoiFile::open(&low_path)?;
+                    let source: Vec<u8> = [pixels.as_ref(), low.as_slice()].concat();
+                    let decoded: Vec<u8> = [qoi_file.pixels.as_slice(), &decoded_low.pixels].concat();
+                    QualityReport::between(&source, &decoded, self.img.width(), CHANNELS as usize)
+                }
+                None => metrics::compare(&self.img, &qoi_file)?,
+            })
+        };
+
+        // QOI can't hold metadata, it is kept next to the encoded file. A stale sidecar would restore the wrong profile.
+        let sidecar: Option<PathBuf> = if self.metadata.is_empty() {
+            fs::remove_file(Metadata::sidecar_path(&encoded_path)).ok();
+            None
+        } else {
+            Some(self.metadata.save_sidecar(&encoded_path)?)
+        };
+
+        Ok(
+            CompressionReport {
+                path: self.path.clone(),
+                encoded_path: encoded_path.to_string_lossy().to_string(),
+                raw_size,
+                qoi_size: bytes,
+                encoded_size,
+                quality,
+                sidecar: sidecar.map(|p| p.to_string_lossy().to_string()),
+                low_plane: low_plane.map(|_| low_path.to_string_lossy().to_string()),
+                grayscale: self.metadata.grayscale,
+                optimize_saved,
+                stats,
+                format,
+                warnings,
+            }
+        )
     }
 }
 
-impl QoiEncode for Data { 
+impl Data {
+
+    /*
+    Encodes every frame of 'sequence' into a sequence container at 'encoded_path'.
+    The colorspace and lossy options apply to every frame, the options a sequence can't hold are reported as skipped.
+     */
+    fn compress_sequence(&self, sequence: &Sequence, encoded_path: PathBuf) -> Result<CompressionReport, QoiError> {
+        let warnings: Vec<String> = self.sequence_warnings();
+
+        let mut sequence: Cow<Sequence> = Cow::Borrowed(sequence);
+        let source_space: ColorSpace = ColorSpace::from_header(sequence.color_space);
+        if self.colorspace() != source_space {
+            for frame in &mut sequence.to_mut().frames {
+                convert(&mut frame.pixels, CHANNELS as usize, source_space, self.colorspace());
+            }
+        }
 
-    // QOI encoding function.
-    fn encode(&self, pixels: &mut Vec<u8>, buffer: &mut BufWriter<File>) -> Result<usize, Error> {
+        // Frames are compared against what they were before the lossy stage.
+        let source: Vec<u8> = sequence.flat_pixels();
+        if let Some(lossy) = &self.options.lossy {
+            let width: u32 = sequence.width;
+            for frame in &mut sequence.to_mut().frames {
+                quantize(&mut frame.pixels, width, CHANNELS as usize, lossy);
+            }
+        }
 
-        let mut written_bytes: usize = 0;
-        let width: u32 = self.img.width();
-        let height: u32 = self.img.height();
+        let mut buf_writer: BufWriter<File> = BufWriter::new(File::create(&encoded_path)?);
+        let bytes: usize = sequence.encode(self.colorspace(), self.options.frame_diff, &mut buf_writer)?;
+        buf_writer.flush()?;
+        drop(buf_writer);
+        let encoded_size: usize = fs::metadata(&encoded_path)?.len() as usize;
+        if bytes != encoded_size {
+            return Err(QoiError::SavingError(format!("{} holds {} bytes, {} were written", encoded_path.display(), encoded_size, bytes)));
+        }
 
-        let mut run: u8 = 0;
-        let last_offset: usize = pixels.len() - CHANNELS as usize;
+        // Every frame is compared, as one tall image.
+        let quality: Option<QualityReport> = if self.options.skip_verify {
+            None
+        } else {
+            let decoded: Sequence = Sequence::open(&encoded_path)?;
+            Some(QualityReport::between(&source, &decoded.flat_pixels(), sequence.width, CHANNELS as usize))
+        };
 
-        let mut prev: Pixel = Pixel::zero();
-        let mut seen_pixels: [Pixel; 64] = [Pixel::zero(); 64];
+        Ok(
+            CompressionReport {
+                path: self.path.clone(),
+                encoded_path: encoded_path.to_string_lossy().to_string(),
+                raw_size: sequence.raw_size(),
+                qoi_size: bytes,
+                encoded_size,
+                quality,
+                sidecar: None,
+                low_plane: None,
+                grayscale: false,
+                optimize_saved: None,
+                stats: None,
+                format: None,
+                warnings,
+            }
+        )
+    }
 
-        #[allow(unused_assignments)]
-        let mut index: usize = usize::MIN;
+    // Options a sequence container has no room for, each skipped with a warning.
+    fn sequence_warnings(&self) -> Vec<String> {
+        let options: &CompressOptions = &self.options;
+        [
+            (options.entropy.is_some(), "second stage"),
+            (options.container, "container trailer"),
+            (options.tile_size.is_some(), "tiles"),
+            (options.split_planes, "split planes"),
+            (options.auto_format.is_some(), "auto format"),
+            (options.optimize, "optimize"),
+            (options.stats, "encoding stats"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| format!("{} skipped, sequences are plain QOI frames", name))
+        .collect()
+    }
 
-        let mut write = |chunk: &[u8]| {
-            written_bytes += chunk.len();
-            buffer.write_all(chunk)
+    /*
+    Writes 'pixels' as the PNG file auto format chose.
+    PNG holds no trailer or colorspace flag, and the optimize mode's transforms and stats only apply to QOI.
+     */
+    fn compress_png(
+        &self, pixels: &[u8], channels: usize, encoded_path: PathBuf, raw_size: usize, format: FormatChoice, mut warnings: Vec<String>
+    ) -> Result<CompressionReport, QoiError> {
+        let encoded: Vec<u8> = OutputFormat::Png.encode(pixels, channels, self.img.width(), self.img.height(), self.colorspace())?;
+        fs::write(&encoded_path, &encoded)?;
+
+        if self.options.container {
+            warnings.push("PNG output gets no trailer".to_string());
+        }
+        if self.options.stats {
+            warnings.push("no encoding stats for PNG output".to_string());
+        }
+        if self.colorspace() == ColorSpace::Linear {
+            warnings.push("PNG has no colorspace flag, linear pixels are stored as they are".to_string());
+        }
+
+        let quality: Option<QualityReport> = if self.options.skip_verify {
+            None
+        } else {
+            let decoded: DynamicImage = image::open(&encoded_path)?;
+            Some(QualityReport::between(&self.img.to_rgba8(), &decoded.to_rgba8(), self.img.width(), 4))
         };
 
-        let offset_pixel = |offset: usize| -> Pixel { 
-            Pixel {
-                r: pixels[offset],
-                g: pixels[offset + 1],
-                b: pixels[offset + 2],
-                a: 255 as u8,
-            }
+        let sidecar: Option<PathBuf> = if self.metadata.is_empty() {
+            fs::remove_file(Metadata::sidecar_path(&encoded_path)).ok();
+            None
+        } else {
+            Some(self.metadata.save_sidecar(&encoded_path)?)
         };
 
-        // Write the header into the buffer.
-        write(&QOI_MAGIC)?;
-        write(&width.to_be_bytes())?;
-        write(&height.to_be_bytes())?;
-        write(&[CHANNELS])?;
-        write(&[COLORSPACE])?;
-
-        for offset in (0..pixels.len()).into_iter().step_by(CHANNELS as usize) {
-
-            let pixel: Pixel = offset_pixel(offset);
-            
-            // Check run.
-            if pixel == prev { 
-                run += 1;
-                if let true = (run == 62 || offset == last_offset) { 
-                    write(&[QOI_OP_RUN | (run - 1)])?; 
-                    run = 0;
-                }
+        Ok(
+            CompressionReport {
+                path: self.path.clone(),
+                encoded_path: encoded_path.to_string_lossy().to_string(),
+                raw_size,
+                // No QOI stream was written, the PNG file stands in for it.
+                qoi_size: encoded.len(),
+                encoded_size: encoded.len(),
+                quality,
+                sidecar: sidecar.map(|p| p.to_string_lossy().to_string()),
+                low_plane: None,
+                grayscale: self.metadata.grayscale,
+                optimize_saved: None,
+                stats: None,
+                format: Some(format),
+                warnings,
             }
+        )
+    }
+}
 
-            // Run existing and the pixel broke the equality.
-            else {
+impl QoiEncode for Data { 
 
-                if run > 0 {
-                    write(&[QOI_OP_RUN | (run - 1)])?; 
-                    run = 0;
-                }
+    // QOI encoding function, 'pixels' holds 'channels' bytes per pixel.
+    fn encode<W: Write>(&self, pixels: &[u8], channels: usize, buffer: &mut W) -> Result<usize, Error> {
 
-                // Check for index chunk.
-                index = pixel.hash() % seen_pixels.len();
-                if pixel == seen_pixels[index] {
-                    write(&[QOI_OP_INDEX | index as u8])?;
-                }
-                else {
-
-                    // Update the array.
-                    seen_pixels[index] = pixel.clone(); 
-  
-                    //Check for different chunks.
-                    let diff_r = pixel.r as i16 - prev.r as i16; 
-                    let diff_g = pixel.g as i16 - prev.g as i16;
-                    let diff_b = pixel.b as i16 - prev.b as i16; 
-
-                    let dr_dg = diff_r - diff_g; 
-                    let db_dg = diff_b - diff_g; 
-
-                    if diff_r > -3 && diff_r < 2 
-                    && diff_g > -3 && diff_g < 2
-                    && diff_b > -3 && diff_b < 2 {
-                        let qoi_diff_chunk: u8 = QOI_OP_DIFF as u8
-                                | ((diff_r + 2) << 4) as u8
-                                | ((diff_g + 2) << 2) as u8
-                                | ((diff_b + 2) << 0) as u8; // Clearer vision of the DIFF chunk.
-                        
-                        write(&[qoi_diff_chunk])?;
-                    }  
-                    else {
-                        if  diff_g > -33 && diff_g < 32 
-                            && dr_dg > -9 && dr_dg < 8
-                            && db_dg > -9 && db_dg < 8 {
-                            let qoi_luma_h: u8 = QOI_OP_LUMA | (diff_g + 32) as u8;
-                            let qoi_luma_l: u8 = ((dr_dg + 8) << 4) as u8
-                                               | ((db_dg + 8) << 0) as u8 ; // Clearer vision of the LUMA chunk.
-                            
-                            write(&[qoi_luma_h])?;
-                            write(&[qoi_luma_l])?;
-                        }
-                        else {
-                            // Write 4 bytes of QOI_OP_RGB 
-                            write(&[QOI_OP_RGB])?;
-                            write(&[pixel.r])?;
-                            write(&[pixel.g])?;
-                            write(&[pixel.b])?;
-                        }
-                    }
-                }
+        let out: Vec<u8> = encode_image(pixels, channels, self.img.width(), self.img.height(), self.colorspace());
+
+        // Single flush of the encoded bytes.
+        buffer.write_all(&out)?;
+        buffer.flush()?;
+        Ok(out.len())
+    }
+}
+
+/*
+Encodes 'pixels' ('channels' bytes per pixel) into a complete QOI stream, header and end mark included.
+ */
+pub fn encode_image(pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace) -> Vec<u8> {
+
+    // Preallocate the worst case, so the hot loop never grows the buffer.
+    let mut out: Vec<u8> = vec![0; max_encoded_size(pixels.len() / channels)];
+
+    let mut pos: usize = write_header(&mut out, width, height, color_space);
+    pos = encode_chunks(pixels, channels, false, &mut out, pos);
+    out[pos..pos + QOI_END_MARK_SIZE].copy_from_slice(&QOI_END_MARK);
+    pos += QOI_END_MARK_SIZE;
+
+    out.truncate(pos);
+    out
+}
+
+// Size of the encoded file if every pixel needed a QOI_OP_RGB chunk.
+pub fn max_encoded_size(pixel_count: usize) -> usize {
+    QOI_HEADER_SIZE + pixel_count * (CHANNELS as usize + 1) + QOI_END_MARK_SIZE
+}
+
+// Writes the QOI header at the start of 'out', returns its size.
+pub fn write_header(out: &mut [u8], width: u32, height: u32, color_space: ColorSpace) -> usize {
+    out[0..4].copy_from_slice(&QOI_MAGIC);
+    out[4..8].copy_from_slice(&width.to_be_bytes());
+    out[8..12].copy_from_slice(&height.to_be_bytes());
+    out[12] = CHANNELS;
+    out[13] = color_space.header_byte();
+    QOI_HEADER_SIZE
+}
+
+/*
+Encodes 'pixels' into chunks written to 'out' from 'pos', returns the position after the last chunk.
+'out' must have room for 4 bytes per pixel.
+Only index entries the stream wrote itself are used, the decoder's start as all zeros.
+A 'detached' stream also starts with a QOI_OP_RGB chunk, so it decodes correctly after any other chunks.
+ */
+pub fn encode_chunks(pixels: &[u8], channels: usize, detached: bool, out: &mut [u8], pos: usize) -> usize {
+    match channels {
+        1 => encode_layout::<1>(pixels, detached, out, pos),
+        2 => encode_layout::<2>(pixels, detached, out, pos),
+        3 => encode_layout::<3>(pixels, detached, out, pos),
+        4 => encode_layout::<4>(pixels, detached, out, pos),
+        _ => panic!("Unsupported amount of channels: {}", channels),
+    }
+}
+
+// Reads the RGB of a pixel laid out with N channels, gray layouts are expanded and alpha is dropped.
+#[inline(always)]
+fn rgb_pixel<const N: usize>(px: &[u8]) -> Pixel {
+    if N < 3 {
+        Pixel { r: px[0], g: px[0], b: px[0], a: 255 }
+    } else {
+        Pixel { r: px[0], g: px[1], b: px[2], a: 255 }
+    }
+}
+
+// Index position of a gray pixel, mirrors Pixel::hash with r = g = b and an alpha of 255.
+#[inline(always)]
+fn gray_hash(value: u8) -> usize {
+    (value as usize * 15 + 255 * 11) % 64
+}
+
+// Writes the chunk of a gray pixel 'diff' away from the previous one, returns the position after it.
+#[inline(always)]
+fn encode_gray_delta(diff: i16, pixel: Pixel, out: &mut [u8], pos: usize) -> usize {
+    if (-2..2).contains(&diff) {
+        let d: u8 = (diff + 2) as u8;
+        out[pos] = QOI_OP_DIFF | d << 4 | d << 2 | d;
+        pos + 1
+    }
+    else if (-32..32).contains(&diff) {
+        // dr - dg and db - dg are always 0.
+        out[pos] = QOI_OP_LUMA | (diff + 32) as u8;
+        out[pos + 1] = 0x88;
+        pos + 2
+    }
+    else {
+        out[pos..pos + 4].copy_from_slice(&[QOI_OP_RGB, pixel.r, pixel.g, pixel.b]);
+        pos + 4
+    }
+}
+
+fn encode_layout<const N: usize>(pixels: &[u8], detached: bool, out: &mut [u8], mut pos: usize) -> usize {
+
+    let mut run: u8 = 0;
+    let mut prev: Pixel = Pixel::zero();
+    let mut seen_pixels: [Pixel; 64] = [Pixel::INDEX_START; 64];
+
+    // Bit i is set once seen_pixels[i] was written by this stream, only then does it match the decoder's index.
+    let mut known: u64 = 0;
+    let mut force_rgb: bool = detached;
+
+    let count: usize = pixels.len() / N;
+    let mut hashes: [u8; HASH_BLOCK] = [0; HASH_BLOCK];
+    let mut block_start: usize = 0;
+    let mut block_len: usize = 0;
+
+    let mut i: usize = 0;
+    while i < count {
+
+        let px: &[u8] = &pixels[i * N..(i + 1) * N];
+        let pixel: Pixel = rgb_pixel::<N>(px);
+
+        // Check run, scanning ahead for every following pixel with the same bytes.
+        if pixel == prev && !force_rgb {
+            let length: usize = 1 + run_length(&pixels[(i + 1) * N..count * N], px);
+            let total: usize = run as usize + length;
+
+            for _ in 0..total / 62 {
+                out[pos] = QOI_OP_RUN | 61;
+                pos += 1;
             }
+            run = (total % 62) as u8;
+            i += length;
+            continue;
+        }
 
-            prev = pixel.clone();
+        // Run existing and the pixel broke the equality.
+        if run > 0 {
+            out[pos] = QOI_OP_RUN | (run - 1);
+            pos += 1;
+            run = 0;
         }
-        write(&QOI_END_MARK)?;
 
-        // Return the number of encoded bytes.
-        buffer.flush()?;
-        Ok(written_bytes)
+        // Hashes are computed a block of pixels at a time, gray ones straight from the value.
+        let index: usize = if N < 3 {
+            gray_hash(px[0])
+        } else {
+            if i >= block_start + block_len {
+                block_start = i;
+                block_len = hash_block(&pixels[i * N..count * N], N, &mut hashes);
+            }
+            hashes[i - block_start] as usize
+        };
+
+        // Check for index chunk.
+        if known & (1 << index) != 0 && pixel == seen_pixels[index] && !force_rgb {
+            out[pos] = QOI_OP_INDEX | index as u8;
+            pos += 1;
+        }
+        else {
+
+            // Update the array.
+            seen_pixels[index] = pixel;
+            known |= 1 << index;
+
+            // Check for different chunks.
+            let diff_r = pixel.r as i16 - prev.r as i16;
+            let diff_g = pixel.g as i16 - prev.g as i16;
+            let diff_b = pixel.b as i16 - prev.b as i16;
+
+            let dr_dg = diff_r - diff_g;
+            let db_dg = diff_b - diff_g;
+
+            if force_rgb {
+                out[pos..pos + 4].copy_from_slice(&[QOI_OP_RGB, pixel.r, pixel.g, pixel.b]);
+                pos += 4;
+                force_rgb = false;
+            }
+            else if N < 3 {
+                // Gray pixels have the same delta on every channel, only dg decides.
+                pos = encode_gray_delta(diff_g, pixel, out, pos);
+            }
+            else if (-2..2).contains(&diff_r) && (-2..2).contains(&diff_g) && (-2..2).contains(&diff_b) {
+                out[pos] = QOI_OP_DIFF | ((diff_r + 2) << 4) as u8 | ((diff_g + 2) << 2) as u8 | (diff_b + 2) as u8;
+                pos += 1;
+            }
+            else if (-32..32).contains(&diff_g) && (-8..8).contains(&dr_dg) && (-8..8).contains(&db_dg) {
+                out[pos] = QOI_OP_LUMA | (diff_g + 32) as u8;
+                out[pos + 1] = ((dr_dg + 8) << 4) as u8 | (db_dg + 8) as u8;
+                pos += 2;
+            }
+            else {
+                out[pos..pos + 4].copy_from_slice(&[QOI_OP_RGB, pixel.r, pixel.g, pixel.b]);
+                pos += 4;
+            }
+        }
 
+        prev = pixel;
+        i += 1;
     }
+
+    // Runs never continue past the given pixels.
+    if run > 0 {
+        out[pos] = QOI_OP_RUN | (run - 1);
+        pos += 1;
+    }
+
+    pos
 }
 
 impl QoiDecode for Data {
 
     fn decode(&self, reader: &mut BufReader<File>, path: PathBuf) -> Result<QoiFile, QoiError> {
+        decode_reader(reader, path)
+    }
+}
 
-        let width: u32 = self.img.width();
-        let height: u32 = self.img.height(); 
+// Reads the whole stream from 'reader' once and decodes it.
+pub fn decode_reader(reader: &mut BufReader<File>, path: PathBuf) -> Result<QoiFile, QoiError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decode_bytes(&bytes, path)
+}
 
-        let mut read_bytes: usize = 0;
-        let mut prev = Pixel::zero();
-        let mut seen_pixels = [Pixel::zero(); 64];
+// Reads the byte at 'pos' and advances it.
+fn next_byte(bytes: &[u8], pos: &mut usize) -> Result<u8, QoiError> {
+    let byte: u8 = *bytes.get(*pos).ok_or_else(|| Error::from(ErrorKind::UnexpectedEof))?;
+    *pos += 1;
+    Ok(byte)
+}
 
-        let mut read_pixels: Vec<Pixel> = Vec::with_capacity((width * height) as usize);
-        let mut buf: Vec<u8> = Vec::new();
+/*
+Decodes the QOI stream in 'bytes' straight into a flat pixel buffer sized from the header.
+zstd and LZ4 compressed streams are detected and decompressed.
+ */
+pub fn decode_bytes(bytes: &[u8], path: PathBuf) -> Result<QoiFile, QoiError> {
 
-        reader.read_to_end(&mut buf)?;
+    // Files with a second compression stage are unwrapped first.
+    let bytes: Cow<[u8]> = entropy::unwrap(bytes)?;
+    let bytes: &[u8] = &bytes;
 
-        // Seek to the start.
-        reader.rewind()?;
+    if bytes.len() < QOI_HEADER_SIZE {
+        return Err(Error::from(ErrorKind::UnexpectedEof).into());
+    }
 
-        let buffered_magic: [u8; 4]  = read_u32(reader, &mut read_bytes)?;
-        let buffered_width: [u8; 4]  = read_u32(reader, &mut read_bytes)?;
-        let buffered_height: [u8; 4] = read_u32(reader, &mut read_bytes)?;
-        let buffered_channels: u8    =  read_u8(reader, &mut read_bytes)?[0];
-        let buffered_color_space: u8 =  read_u8(reader, &mut read_bytes)?[0];
+    // Check MAGIC header.
+    if bytes[0..4] != QOI_MAGIC {
+        let magic_error: String = format!("{:?}", &bytes[0..4]);
+        return Err(QoiError::InvalidHeader(magic_error));
+    }
 
-        // Check MAGIC header.
-        if buffered_magic != QOI_MAGIC {
-            let magic_error: String = format!("{:?}", buffered_magic);
-            panic!("{}", QoiError::InvalidHeader(magic_error));
-        }
+    let width: u32 = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let height: u32 = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let channels: u8 = bytes[12];
+    let color_space: u8 = bytes[13];
 
-        while read_bytes < buf.len() - QOI_END_MARK_SIZE {
+    // A run chunk holds at most 62 pixels, a header promising more than the bytes can hold is corrupt.
+    if width as u64 * height as u64 > (bytes.len() - QOI_HEADER_SIZE) as u64 * 62 {
+        return Err(QoiError::DimensionMismatch(format!("{}x{} pixels don't fit in {} bytes", width, height, bytes.len())));
+    }
 
-            let current_byte: u8 = read_u8(reader, &mut read_bytes)?[0];
+    // Anything but RGBA is decoded as RGB.
+    let out_channels: usize = if channels == 4 { 4 } else { CHANNELS as usize };
+    let mut pixels: Vec<u8> = vec![0; width as usize * height as usize * out_channels];
 
-            // Check single encoded pixel.
-            if current_byte == QOI_OP_RGB {
-                let r: u8 = read_u8(reader, &mut read_bytes)?[0];
-                let g: u8 = read_u8(reader, &mut read_bytes)?[0];
-                let b: u8 = read_u8(reader, &mut read_bytes)?[0];
+    let mut pos: usize = QOI_HEADER_SIZE;
+    let mut run: u8 = 0;
+    let mut prev: Pixel = Pixel::zero();
+    let mut seen_pixels: [Pixel; 64] = [Pixel::INDEX_START; 64];
 
-                prev.r = r;
-                prev.g = g;
-                prev.b = b;
- 
-                let index = prev.hash() % seen_pixels.len();
-                seen_pixels[index] = prev;
-                read_pixels.push(prev);
- 
-                continue;
+    for out in pixels.chunks_exact_mut(out_channels) {
+
+        if run > 0 {
+            run -= 1;
+        }
+        else {
+            let current_byte: u8 = next_byte(bytes, &mut pos)?;
+
+            if current_byte == QOI_OP_RGB {
+                prev.r = next_byte(bytes, &mut pos)?;
+                prev.g = next_byte(bytes, &mut pos)?;
+                prev.b = next_byte(bytes, &mut pos)?;
+            }
+            else if current_byte == QOI_OP_RGBA {
+                prev.r = next_byte(bytes, &mut pos)?;
+                prev.g = next_byte(bytes, &mut pos)?;
+                prev.b = next_byte(bytes, &mut pos)?;
+                prev.a = next_byte(bytes, &mut pos)?;
             }
+            else {
+                match current_byte & QOI_2BIT_TAG_MASK {
+                    QOI_OP_INDEX => {
+                        prev = seen_pixels[(current_byte & QOI_INDEX_VALUE_MASK) as usize];
+                    }
+                    QOI_OP_DIFF => {
+                        let diff_r: u8 = ((current_byte & QOI_RED_DIFF)   >> 4).wrapping_sub(2);
+                        let diff_g: u8 = ((current_byte & QOI_GREEN_DIFF) >> 2).wrapping_sub(2);
+                        let diff_b: u8 = (current_byte & QOI_BLUE_DIFF).wrapping_sub(2);
+
+                        prev.r = prev.r.wrapping_add(diff_r);
+                        prev.g = prev.g.wrapping_add(diff_g);
+                        prev.b = prev.b.wrapping_add(diff_b);
+                    }
+                    QOI_OP_LUMA => {
+                        let diff_g: u8 = (current_byte & QOI_LUMA_DG).wrapping_sub(32);
+                        let next_byte: u8 = next_byte(bytes, &mut pos)?;
 
-            // Check run.
-            if (current_byte & QOI_2BIT_TAG_MASK) == QOI_OP_RUN { 
-                let mut run_value: u8 = (current_byte & QOI_RUN_LENGTH_MASK) + 1;
+                        let dr_dg: u8 = ((next_byte & QOI_LUMA_DRDG_MASK) >> 4).wrapping_sub(8); // higher half
+                        let db_dg: u8 = (next_byte & QOI_LUMA_DBDG_MASK).wrapping_sub(8); // lower half
 
-                // Check if QOI file starts with a run.
-                if read_bytes == QOI_HEADER_SIZE + 1 as usize {
-                    let index: usize = Pixel::zero().hash() % seen_pixels.len();
-                    seen_pixels[index] = Pixel::zero(); 
+                        prev.r = prev.r.wrapping_add(dr_dg.wrapping_add(diff_g));
+                        prev.g = prev.g.wrapping_add(diff_g);
+                        prev.b = prev.b.wrapping_add(db_dg.wrapping_add(diff_g));
+                    }
+                    _ => {
+                        // QOI_OP_RUN, this pixel is the first of the run.
+                        run = current_byte & QOI_RUN_LENGTH_MASK;
+                    }
                 }
+            }
 
-                while run_value > 0 {
-                    read_pixels.push(prev);
-                    run_value -= 1;
-                }
+            seen_pixels[prev.hash() % seen_pixels.len()] = prev;
+        }
 
-                continue;
-            }
+        out[..CHANNELS as usize].copy_from_slice(&prev.to_bytes());
+        if out_channels == 4 {
+            out[3] = prev.a;
+        }
+    }
 
-            if (current_byte & QOI_2BIT_TAG_MASK) == QOI_OP_INDEX {
-                let index: u8 = current_byte & QOI_INDEX_VALUE_MASK;
-                let px: Pixel = seen_pixels[index as usize];
-                read_pixels.push(px);
+    let buffered_end_mark: &[u8] = bytes
+        .get(pos..pos + QOI_END_MARK_SIZE)
+        .ok_or_else(|| Error::from(ErrorKind::UnexpectedEof))?;
+    if buffered_end_mark != QOI_END_MARK {
+        let end_mark_alert: String = format!("{:?}", buffered_end_mark);
+        return Err(QoiError::InvalidEndMark(end_mark_alert));
+    }
 
-                prev = px;
+    // Qross containers carry a trailer after the end mark.
+    let trailer: Option<Trailer> = Trailer::parse(&bytes[pos + QOI_END_MARK_SIZE..])?;
+    if let Some(trailer) = &trailer {
+        trailer.verify(&pixels, out_channels)?;
+    }
 
-                continue;
-            }
+    Ok(
+        QoiFile {
+            path,
+            size: 0,
+            width,
+            height,
+            channels: out_channels as u8,
+            color_space,
+            pixels,
+            trailer,
+        }
+    )
+}
+
+// Synthetic images shared by the tests of every module.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::path::PathBuf;
+    use super::encode_image;
+    use crate::colorspace::ColorSpace;
+
+    // Gradient with a flat band and noise, so every op shows up, alpha is 200.
+    pub fn sample(width: u32, height: u32, channels: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                let value: u8 = if y % 4 == 0 { 80 } else { (x * 3 + y) as u8 ^ (i % 5) as u8 };
+                [value, value.wrapping_add(x as u8), value / 2, 200][..channels].to_vec()
+            })
+            .collect()
+    }
+
+    // Complete QOI stream of the RGB sample.
+    pub fn stream(width: u32, height: u32) -> Vec<u8> {
+        encode_image(&sample(width, height, 3), 3, width, height, ColorSpace::Srgb)
+    }
 
-            if (current_byte & QOI_2BIT_TAG_MASK) == QOI_OP_DIFF {
+    // Path in the temp folder, unique to the module and the test run.
+    pub fn temp_file(module: &str, name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qross_{}_{}_{}", module, std::process::id(), name))
+    }
+}
 
-                let diff_r: u8 = ((current_byte & QOI_RED_DIFF)   >> 4).wrapping_sub(2);
-                let diff_g: u8 = ((current_byte & QOI_GREEN_DIFF) >> 2).wrapping_sub(2);
-                let diff_b: u8 = ((current_byte & QOI_BLUE_DIFF)  >> 0).wrapping_sub(2);
-                
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::fixtures::{sample, temp_file};
 
-                // Prev pixel whom diff was calculated.
-                let pixel: Pixel = Pixel {
-                    r: (diff_r.wrapping_add(prev.r)), 
-                    g: (diff_g.wrapping_add(prev.g)), 
-                    b: (diff_b.wrapping_add(prev.b)),
-                    a: 255, 
-                };
+    #[test]
+    fn decode_reads_back_a_flat_rgb_buffer() {
+        let pixels: Vec<u8> = sample(33, 17, 3);
+        let decoded: QoiFile = decode_bytes(&encode_image(&pixels, 3, 33, 17, ColorSpace::Linear), PathBuf::new()).unwrap();
 
-                read_pixels.push(pixel);
+        assert_eq!((decoded.width, decoded.height, decoded.channels), (33, 17, 3));
+        assert_eq!(decoded.colorspace(), ColorSpace::Linear);
+        assert_eq!(decoded.pixels, pixels);
+        assert!(decoded.trailer.is_none());
+    }
 
-                let index = pixel.hash() % seen_pixels.len();
-                seen_pixels[index] = pixel;
-                prev = pixel;
+    #[test]
+    fn decode_rejects_a_short_header_and_a_wrong_magic() {
+        let encoded: Vec<u8> = encode_image(&sample(4, 4, 3), 3, 4, 4, ColorSpace::Srgb);
 
-                continue;
-            } 
-            if (current_byte & QOI_2BIT_TAG_MASK) == QOI_OP_LUMA {
-                
-                let diff_g: u8 = (current_byte & QOI_LUMA_DG).wrapping_sub(32);
-                let next_byte: u8 = read_u8(reader, &mut read_bytes)?[0];
+        assert!(matches!(decode_bytes(&encoded[..QOI_HEADER_SIZE - 1], PathBuf::new()), Err(QoiError::GeneralIOError(_))));
 
-                let dr_dg: u8 = ((next_byte & QOI_LUMA_DRDG_MASK) >> 4).wrapping_sub(8); // higher half
-                let db_dg: u8 = ((next_byte & QOI_LUMA_DBDG_MASK) >> 0).wrapping_sub(8); // lower half
+        let mut wrong_magic: Vec<u8> = encoded.clone();
+        wrong_magic[0] = b'x';
+        assert!(matches!(decode_bytes(&wrong_magic, PathBuf::new()), Err(QoiError::InvalidHeader(_))));
+    }
 
-                let diff_r: u8 = dr_dg.wrapping_add(diff_g);
-                let diff_b: u8 = db_dg.wrapping_add(diff_g); 
+    #[test]
+    fn decode_rejects_cut_streams_and_impossible_sizes() {
+        let encoded: Vec<u8> = encode_image(&sample(16, 16, 3), 3, 16, 16, ColorSpace::Srgb);
 
-                // Prev pixel for whom luma was calculated.
-                let pixel: Pixel = Pixel {
-                    r: (diff_r.wrapping_add(prev.r)), 
-                    g: (diff_g.wrapping_add(prev.g)), 
-                    b: (diff_b.wrapping_add(prev.b)),
-                    a: 255, 
-                };
+        // Cut inside the chunks, then inside the end mark.
+        assert!(matches!(decode_bytes(&encoded[..encoded.len() / 2], PathBuf::new()), Err(QoiError::GeneralIOError(_))));
+        assert!(decode_bytes(&encoded[..encoded.len() - 2], PathBuf::new()).is_err());
 
-                read_pixels.push(pixel);
+        let mut wrong_end: Vec<u8> = encoded.clone();
+        *wrong_end.last_mut().unwrap() = 7;
+        assert!(matches!(decode_bytes(&wrong_end, PathBuf::new()), Err(QoiError::InvalidEndMark(_))));
 
-                let index = pixel.hash() % seen_pixels.len();
-                seen_pixels[index ] = pixel;
-                prev = pixel;
+        // A header promising more pixels than the chunks can hold isn't allocated.
+        let mut huge: Vec<u8> = encoded.clone();
+        huge[4..12].copy_from_slice(&[0xFF; 8]);
+        assert!(matches!(decode_bytes(&huge, PathBuf::new()), Err(QoiError::DimensionMismatch(_))));
+    }
 
-                continue;
-            }
+    #[test]
+    fn gray_fast_path_writes_the_rgb_stream() {
+        for channels in [1, 2] {
+            let gray: Vec<u8> = sample(41, 13, channels);
+            let expanded: Vec<u8> = gray.chunks_exact(channels).flat_map(|px| [px[0]; 3]).collect();
+            assert_eq!(
+                encode_image(&gray, channels, 41, 13, ColorSpace::Srgb),
+                encode_image(&expanded, 3, 41, 13, ColorSpace::Srgb),
+                "{} channels", channels
+            );
         }
+    }
 
-        let buffered_end_mark: [u8; 8] = read_from_buffer::<8>(reader, &mut read_bytes)?;
-        if buffered_end_mark != QOI_END_MARK { 
-            let end_mark_alert: String = format!("{:?}", buffered_end_mark);
-            panic!("{}", QoiError::InvalidEndMark(end_mark_alert));
+    #[test]
+    fn gray_hash_matches_the_pixel_hash() {
+        for value in 0..=255 {
+            assert_eq!(gray_hash(value), Pixel { r: value, g: value, b: value, a: 255 }.hash() % 64);
         }
+    }
 
-        Ok(
-            QoiFile {
-                path,
-                size: 0,
-                width: u32::from_be_bytes(buffered_width), 
-                height: u32::from_be_bytes(buffered_height),
-                channels: buffered_channels,
-                color_space: buffered_color_space,
-                pixels: read_pixels
-            }
-        )
+    #[test]
+    fn gray_sources_are_flagged_for_export() {
+        let path: PathBuf = temp_file("comp", "gray.png");
+        image::GrayImage::from_fn(8, 8, |x, y| image::Luma([(x * y) as u8])).save(&path).unwrap();
+
+        let data: Data = Data::open(path.to_str().unwrap(), &CompressOptions::default()).unwrap();
+        assert!(data.metadata.grayscale);
+        assert_eq!(data.pixel_slice().1, 1);
+        fs::remove_file(path).ok();
     }
 }
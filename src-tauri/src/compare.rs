@@ -0,0 +1,131 @@
+/*
+Format comparison: encodes an image as QOI, as PNG at three levels and as lossless WebP,
+so users can see whether QOI is the right choice for their images.
+*/
+
+use std::fmt;
+use std::path::Path;
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::bench::{measure, Codec, Measurement};
+use crate::comp::FileError;
+use crate::qoi_errror::QoiError;
+
+// Single format's outcome, sizes in bytes and times in milliseconds.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FormatResult {
+    pub codec: Codec,
+    pub size: usize,
+    pub bytes_per_pixel: f64,
+    pub encode_ms: f64,
+    pub decode_ms: f64,
+}
+
+impl From<&Measurement> for FormatResult {
+    fn from(measurement: &Measurement) -> Self {
+        Self {
+            codec: measurement.codec,
+            size: measurement.encoded_size,
+            bytes_per_pixel: measurement.bytes_per_pixel(),
+            encode_ms: measurement.encode_time.as_secs_f64() * 1000.0,
+            decode_ms: measurement.decode_time.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Comparison {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub raw_size: usize,
+    // In the order of Codec::FORMATS, QOI first.
+    pub results: Vec<FormatResult>,
+}
+
+impl Comparison {
+
+    /*
+    Encodes and decodes the image at 'path' in every format, keeping the fastest of 'iterations' runs.
+     */
+    pub fn run(path: &Path, iterations: usize) -> Result<Self, QoiError> {
+        let img: RgbImage = image::open(path)?.to_rgb8();
+        let name: String = path.display().to_string();
+
+        let results: Vec<FormatResult> = Codec::FORMATS
+            .iter()
+            .map(|codec| measure(&name, &img, *codec, iterations).map(|m| FormatResult::from(&m)))
+            .collect::<Result<Vec<FormatResult>, QoiError>>()?;
+
+        Ok(Self { path: name, width: img.width(), height: img.height(), raw_size: img.as_raw().len(), results })
+    }
+
+    // Compares every file in 'files', returns a comparison or the error of every file, in order.
+    pub fn run_all(files: &[String], iterations: usize) -> Vec<Result<Self, FileError>> {
+        files
+            .iter()
+            .map(|file| Self::run(Path::new(file), iterations).map_err(|error| FileError { path: file.clone(), error }))
+            .collect()
+    }
+
+    pub fn smallest(&self) -> Option<&FormatResult> {
+        self.results.iter().min_by_key(|r| r.size)
+    }
+
+    pub fn fastest(&self) -> Option<&FormatResult> {
+        self.results.iter().min_by(|a, b| (a.encode_ms + a.decode_ms).total_cmp(&(b.encode_ms + b.decode_ms)))
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} ({}x{}, {} bytes raw)", self.path, self.width, self.height, self.raw_size)?;
+        let qoi_size: usize = self.results.first().map(|r| r.size).unwrap_or(0);
+        for result in &self.results {
+            let relative: f64 = 100.0 * result.size as f64 / qoi_size.max(1) as f64;
+            writeln!(
+                f, "  {:<14} {:>12} bytes {:>6.1}% of QOI {:>7.3} B/px {:>9.2} ms encode {:>9.2} ms decode",
+                result.codec.name(), result.size, relative, result.bytes_per_pixel, result.encode_ms, result.decode_ms
+            )?;
+        }
+        if let (Some(smallest), Some(fastest)) = (self.smallest(), self.fastest()) {
+            write!(f, "  smallest {}, fastest {}", smallest.codec.name(), fastest.codec.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::comp::fixtures::{sample, temp_file};
+
+    #[test]
+    fn every_format_is_compared() {
+        let path: PathBuf = temp_file("compare", "sample.png");
+        RgbImage::from_raw(32, 16, sample(32, 16, 3)).unwrap().save(&path).unwrap();
+        let comparison: Result<Comparison, QoiError> = Comparison::run(&path, 1);
+        std::fs::remove_file(&path).ok();
+        let comparison: Comparison = comparison.unwrap();
+
+        assert_eq!((comparison.width, comparison.height, comparison.raw_size), (32, 16, 32 * 16 * 3));
+        assert_eq!(comparison.results.iter().map(|r| r.codec).collect::<Vec<Codec>>(), Codec::FORMATS);
+        assert!(comparison.results.iter().all(|r| r.size > 0 && r.bytes_per_pixel > 0.0));
+        assert!(comparison.smallest().is_some() && comparison.fastest().is_some());
+    }
+
+    #[test]
+    fn unreadable_files_fail_alone() {
+        let path: PathBuf = temp_file("compare", "alone.png");
+        RgbImage::from_raw(8, 8, sample(8, 8, 3)).unwrap().save(&path).unwrap();
+        let files: Vec<String> = vec!["missing.png".to_string(), path.display().to_string()];
+        let results: Vec<Result<Comparison, FileError>> = Comparison::run_all(&files, 1);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap_err().path, "missing.png");
+        assert!(results[1].is_ok());
+    }
+}
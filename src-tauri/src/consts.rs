@@ -5,16 +5,26 @@ pub const IMG_FOLDER_PATH: &str = r"C:\coding\RUST\image_compressor\src\app_imgs
 // Important QOI file fields.
 pub const QOI_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'f'];
 pub const QOI_END_MARK: [u8; 8] = [0b0, 0b0, 0b0, 0b0, 0b0, 0b0, 0b0, 0b1];
+pub const QOI_TRAILER_MAGIC: [u8; 4] = [b'q', b'r', b's', b's'];
+pub const QOI_TILED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b't'];
+pub const QOI_SEQUENCE_MAGIC: [u8; 4] = [b'q', b'o', b'i', b's'];
 
 // One-byte header fields.
 pub const CHANNELS: u8 = 3;
-pub const COLORSPACE: u8 = 1;
+
+// Colorspace header values.
+pub const QOI_SRGB:   u8 = 0;
+pub const QOI_LINEAR: u8 = 1;
+
+// Largest image the reference decoder accepts.
+pub const QOI_PIXELS_MAX: usize = 400_000_000;
 
 pub const QOI_HEADER_SIZE: usize = [u8::MIN; 14].len();
 pub const QOI_END_MARK_SIZE: usize = [u8::MIN; 8].len();
 
 // Tags
 pub const QOI_OP_RGB:   u8 = 0b11111110;
+pub const QOI_OP_RGBA:  u8 = 0b11111111;
 pub const QOI_OP_INDEX: u8 = 0b00000000;
 pub const QOI_OP_DIFF:  u8 = 0b01000000;
 pub const QOI_OP_RUN:   u8 = 0b11000000;
@@ -0,0 +1,247 @@
+/*
+Qross container trailer, written after QOI_END_MARK.
+Standard decoders stop at the end mark and never see it, the Qross decoder validates it.
+
+Layout: QOI_TRAILER_MAGIC, then chunks of a 4 byte tag, a big endian u32 length and the data.
+Unknown tags are skipped, so newer trailers still open.
+*/
+
+use std::io::Write;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crc32fast::Hasher;
+
+use crate::consts::*;
+use crate::optimize::ColorTransform;
+use crate::qoi_errror::QoiError;
+
+// CRC32 of the decoded RGB pixels.
+const TAG_PIXEL_CRC: [u8; 4] = *b"PCRC";
+// CRC32 of the source file.
+const TAG_SOURCE_HASH: [u8; 4] = *b"SRCH";
+// One key/value pair, both UTF-8 and separated by a zero byte.
+const TAG_ENTRY: [u8; 4] = *b"META";
+// Color transform of the optimize mode, the pixel checksum is taken before undoing it.
+const TAG_COLOR_TRANSFORM: [u8; 4] = *b"CTRN";
+
+const CHUNK_HEADER_SIZE: usize = 8;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Trailer {
+    pub pixel_crc: u32,
+    pub source_hash: Option<u32>,
+    pub entries: Vec<(String, String)>,
+    pub color_transform: Option<ColorTransform>,
+}
+
+impl Trailer {
+
+    /*
+    Returns the trailer of 'pixels' ('channels' bytes per pixel) encoded from the file at 'source'.
+     */
+    pub fn new(pixels: &[u8], channels: usize, source: &Path) -> Self {
+        let source_hash: Option<u32> = std::fs::read(source).ok().map(|bytes| crc32fast::hash(&bytes));
+        let mut entries: Vec<(String, String)> = vec![
+            ("encoder".to_string(), "qross".to_string()),
+            ("created".to_string(), chrono::Utc::now().to_rfc3339()),
+        ];
+        if let Some(name) = source.file_name() {
+            entries.push(("source".to_string(), name.to_string_lossy().to_string()));
+        }
+
+        Self { pixel_crc: pixel_crc(pixels, channels), source_hash, entries, color_transform: None }
+    }
+
+    // Value of the entry 'key'.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = QOI_TRAILER_MAGIC.to_vec();
+        write_chunk(&mut out, TAG_PIXEL_CRC, &self.pixel_crc.to_be_bytes());
+        if let Some(hash) = self.source_hash {
+            write_chunk(&mut out, TAG_SOURCE_HASH, &hash.to_be_bytes());
+        }
+        if let Some(transform) = &self.color_transform {
+            write_chunk(&mut out, TAG_COLOR_TRANSFORM, &transform.to_bytes());
+        }
+        for (key, value) in &self.entries {
+            write_chunk(&mut out, TAG_ENTRY, &[key.as_bytes(), &[0], value.as_bytes()].concat());
+        }
+        out
+    }
+
+    // Writes the trailer into 'buffer', returns the number of written bytes.
+    pub fn write<W: Write>(&self, buffer: &mut W) -> Result<usize, std::io::Error> {
+        let bytes: Vec<u8> = self.to_bytes();
+        buffer.write_all(&bytes)?;
+        buffer.flush()?;
+        Ok(bytes.len())
+    }
+
+    /*
+    Parses the bytes following the end mark, None when they don't hold a trailer.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>, QoiError> {
+        if !bytes.starts_with(&QOI_TRAILER_MAGIC) {
+            return Ok(None);
+        }
+
+        let mut trailer: Trailer = Trailer::default();
+        let mut has_crc: bool = false;
+        let mut pos: usize = QOI_TRAILER_MAGIC.len();
+
+        while pos < bytes.len() {
+            let truncated = || QoiError::InvalidTrailer(format!("chunk at byte {} is truncated", pos));
+            let header: &[u8] = bytes.get(pos..pos + CHUNK_HEADER_SIZE).ok_or_else(truncated)?;
+            let tag: [u8; 4] = [header[0], header[1], header[2], header[3]];
+            let len: usize = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let data: &[u8] = bytes.get(pos + CHUNK_HEADER_SIZE..pos + CHUNK_HEADER_SIZE + len).ok_or_else(truncated)?;
+
+            match tag {
+                TAG_PIXEL_CRC => {
+                    trailer.pixel_crc = read_u32(data)?;
+                    has_crc = true;
+                }
+                TAG_SOURCE_HASH => trailer.source_hash = Some(read_u32(data)?),
+                TAG_COLOR_TRANSFORM => {
+                    trailer.color_transform = Some(ColorTransform::from_bytes(data)
+                        .ok_or_else(|| QoiError::InvalidTrailer(format!("invalid color transform {:?}", data)))?);
+                }
+                TAG_ENTRY => {
+                    let split: usize = data.iter().position(|b| *b == 0)
+                        .ok_or_else(|| QoiError::InvalidTrailer("entry without a key".to_string()))?;
+                    trailer.entries.push((
+                        String::from_utf8_lossy(&data[..split]).to_string(),
+                        String::from_utf8_lossy(&data[split + 1..]).to_string(),
+                    ));
+                }
+                _ => (),
+            }
+            pos += CHUNK_HEADER_SIZE + len;
+        }
+
+        if !has_crc {
+            return Err(QoiError::InvalidTrailer("missing pixel checksum".to_string()));
+        }
+        Ok(Some(trailer))
+    }
+
+    // Checks the decoded RGB(A) 'pixels' against the stored checksum.
+    pub fn verify(&self, pixels: &[u8], channels: usize) -> Result<(), QoiError> {
+        let actual: u32 = pixel_crc(pixels, channels);
+        if actual != self.pixel_crc {
+            return Err(QoiError::ChecksumMismatch(format!("expected {:08x}, found {:08x}", self.pixel_crc, actual)));
+        }
+        Ok(())
+    }
+}
+
+/*
+CRC32 of the RGB values of 'pixels' ('channels' bytes per pixel), the way the decoder sees them.
+Gray layouts are expanded and alpha is left out, as the encoder does.
+ */
+pub fn pixel_crc(pixels: &[u8], channels: usize) -> u32 {
+    if channels == CHANNELS as usize {
+        return crc32fast::hash(pixels);
+    }
+
+    let mut hasher: Hasher = Hasher::new();
+    for px in pixels.chunks_exact(channels) {
+        if channels < 3 {
+            hasher.update(&[px[0], px[0], px[0]]);
+        } else {
+            hasher.update(&px[..3]);
+        }
+    }
+    hasher.finalize()
+}
+
+fn write_chunk(out: &mut Vec<u8>, tag: [u8; 4], data: &[u8]) {
+    out.extend_from_slice(&tag);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn read_u32(data: &[u8]) -> Result<u32, QoiError> {
+    let bytes: [u8; 4] = data.try_into()
+        .map_err(|_| QoiError::InvalidTrailer(format!("expected 4 bytes, found {}", data.len())))?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::comp::{decode_bytes, encode_image};
+    use crate::colorspace::ColorSpace;
+
+    fn sample() -> Vec<u8> {
+        (0..12 * 12 * 3).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn trailer() -> Trailer {
+        Trailer {
+            pixel_crc: pixel_crc(&sample(), 3),
+            source_hash: Some(0xDEADBEEF),
+            entries: vec![("encoder".to_string(), "qross".to_string()), ("note".to_string(), "a\u{e9}".to_string())],
+            color_transform: Some(ColorTransform { order: [1, 0, 2], subtract_green: true }),
+        }
+    }
+
+    #[test]
+    fn trailer_round_trip() {
+        let parsed: Trailer = Trailer::parse(&trailer().to_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, trailer());
+        assert_eq!(parsed.get("note"), Some("a\u{e9}"));
+        assert!(parsed.verify(&sample(), 3).is_ok());
+
+        // Bytes of a plain QOI file end at the end mark.
+        assert_eq!(Trailer::parse(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_chunks_are_skipped() {
+        let mut bytes: Vec<u8> = trailer().to_bytes();
+        write_chunk(&mut bytes, *b"NEXT", &[1, 2, 3]);
+        assert_eq!(Trailer::parse(&bytes).unwrap().unwrap(), trailer());
+    }
+
+    #[test]
+    fn broken_trailers_are_rejected() {
+        let bytes: Vec<u8> = trailer().to_bytes();
+        assert!(matches!(Trailer::parse(&bytes[..bytes.len() - 1]), Err(QoiError::InvalidTrailer(_))));
+
+        let mut without_crc: Vec<u8> = QOI_TRAILER_MAGIC.to_vec();
+        write_chunk(&mut without_crc, TAG_ENTRY, b"key\0value");
+        assert!(matches!(Trailer::parse(&without_crc), Err(QoiError::InvalidTrailer(_))));
+
+        let mut bad_transform: Vec<u8> = QOI_TRAILER_MAGIC.to_vec();
+        write_chunk(&mut bad_transform, TAG_PIXEL_CRC, &[0; 4]);
+        write_chunk(&mut bad_transform, TAG_COLOR_TRANSFORM, &[0, 0, 0, 0]);
+        assert!(matches!(Trailer::parse(&bad_transform), Err(QoiError::InvalidTrailer(_))));
+    }
+
+    #[test]
+    fn checksum_mismatch_fails_the_decode() {
+        let pixels: Vec<u8> = sample();
+        let encoded: Vec<u8> = encode_image(&pixels, 3, 12, 12, ColorSpace::Srgb);
+        let trailer: Trailer = Trailer { pixel_crc: pixel_crc(&pixels, 3), ..Default::default() };
+
+        let file: Vec<u8> = [encoded.as_slice(), &trailer.to_bytes()].concat();
+        assert_eq!(decode_bytes(&file, PathBuf::new()).unwrap().trailer, Some(trailer.clone()));
+
+        let wrong: Trailer = Trailer { pixel_crc: trailer.pixel_crc ^ 1, ..trailer };
+        let file: Vec<u8> = [encoded.as_slice(), &wrong.to_bytes()].concat();
+        assert!(matches!(decode_bytes(&file, PathBuf::new()), Err(QoiError::ChecksumMismatch(_))));
+    }
+
+    #[test]
+    fn crc_ignores_alpha_and_expands_gray() {
+        let rgb: Vec<u8> = vec![10, 20, 30, 40, 50, 60];
+        let rgba: Vec<u8> = vec![10, 20, 30, 0, 40, 50, 60, 255];
+        assert_eq!(pixel_crc(&rgb, 3), pixel_crc(&rgba, 4));
+        assert_eq!(pixel_crc(&[9, 9, 9], 3), pixel_crc(&[9, 128], 2));
+    }
+}
@@ -62,4 +62,132 @@ impl DbFunctions for Table {
         
         Ok(files)
     }
-}  
\ No newline at end of file
+}
+
+// Metadata of a source file, stored as the sidecar's JSON.
+pub trait MetadataFunctions {
+    fn save_metadata(&self, file: &str, metadata: &str) -> Result<(), Error>;
+    fn fetch_metadata(&self, file: &str) -> Result<Option<String>, Error>;
+}
+
+impl MetadataFunctions for Table {
+
+    fn save_metadata(&self, file: &str, metadata: &str) -> Result<(), Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("INSERT INTO {} (file_path, metadata) VALUES (?1, ?2)", &self.table_name);
+        con.execute(&query, [file, metadata])?;
+
+        Ok(())
+    }
+
+    // Latest metadata saved for 'file'.
+    fn fetch_metadata(&self, file: &str) -> Result<Option<String>, Error> {
+        let con = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("SELECT metadata FROM {} WHERE file_path = ?1 ORDER BY rowid DESC LIMIT 1", &self.table_name);
+        let mut statement = con.prepare(&query)?;
+        let mut rows = statement.query_map([file], |r| r.get(0))?;
+
+        rows.next().transpose()
+    }
+}
+
+// Named presets, stored as the JSON of their CompressOptions.
+pub trait PresetFunctions {
+    fn insert_preset(&self, name: &str, options: &str) -> Result<(), Error>;
+    fn save_preset(&self, name: &str, options: &str) -> Result<(), Error>;
+    fn update_preset(&self, name: &str, options: &str) -> Result<bool, Error>;
+    fn delete_preset(&self, name: &str) -> Result<bool, Error>;
+    fn fetch_preset(&self, name: &str) -> Result<Option<String>, Error>;
+    fn fetch_all_presets(&self) -> Result<Vec<(String, String)>, Error>;
+}
+
+impl PresetFunctions for Table {
+
+    // Fails when a preset called 'name' already exists.
+    fn insert_preset(&self, name: &str, options: &str) -> Result<(), Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("INSERT INTO {} (name, options) VALUES (?1, ?2)", &self.table_name);
+        con.execute(&query, [name, options])?;
+
+        Ok(())
+    }
+
+    // Inserts or replaces.
+    fn save_preset(&self, name: &str, options: &str) -> Result<(), Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("INSERT OR REPLACE INTO {} (name, options) VALUES (?1, ?2)", &self.table_name);
+        con.execute(&query, [name, options])?;
+
+        Ok(())
+    }
+
+    // Returns false when there is no preset called 'name'.
+    fn update_preset(&self, name: &str, options: &str) -> Result<bool, Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("UPDATE {} SET options = ?2 WHERE name = ?1", &self.table_name);
+        Ok(con.execute(&query, [name, options])? > 0)
+    }
+
+    fn delete_preset(&self, name: &str) -> Result<bool, Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("DELETE FROM {} WHERE name = ?1", &self.table_name);
+        Ok(con.execute(&query, [name])? > 0)
+    }
+
+    fn fetch_preset(&self, name: &str) -> Result<Option<String>, Error> {
+        let con = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("SELECT options FROM {} WHERE name = ?1", &self.table_name);
+        let mut statement = con.prepare(&query)?;
+        let mut rows = statement.query_map([name], |r| r.get(0))?;
+
+        rows.next().transpose()
+    }
+
+    fn fetch_all_presets(&self) -> Result<Vec<(String, String)>, Error> {
+        let con = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("SELECT name, options FROM {} ORDER BY name", &self.table_name);
+        let mut statement = con.prepare(&query)?;
+        let presets = statement
+            .query_map((), |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, Error>>()?;
+
+        Ok(presets)
+    }
+}
+
+// Output format auto format picked for a source file.
+pub trait FormatFunctions {
+    fn save_format(&self, file: &str, format: &str, encoded_path: &str) -> Result<(), Error>;
+    fn fetch_format(&self, file: &str) -> Result<Option<String>, Error>;
+}
+
+impl FormatFunctions for Table {
+
+    fn save_format(&self, file: &str, format: &str, encoded_path: &str) -> Result<(), Error> {
+        let con: Connection = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("INSERT INTO {} (file_path, format, encoded_path) VALUES (?1, ?2, ?3)", &self.table_name);
+        con.execute(&query, [file, format, encoded_path])?;
+
+        Ok(())
+    }
+
+    // Latest format saved for 'file'.
+    fn fetch_format(&self, file: &str) -> Result<Option<String>, Error> {
+        let con = Connection::open(DB_FILE_NAME)?;
+
+        let query = format!("SELECT format FROM {} WHERE file_path = ?1 ORDER BY rowid DESC LIMIT 1", &self.table_name);
+        let mut statement = con.prepare(&query)?;
+        let mut rows = statement.query_map([file], |r| r.get(0))?;
+
+        rows.next().transpose()
+    }
+}
@@ -0,0 +1,170 @@
+/*
+High bit depth sources (16 bit PNGs, float EXR/HDR) and how they are brought down to the 8 bits QOI stores.
+The split plane mode keeps 16 bit sources lossless: the high bytes are the encoded image itself,
+the low bytes go into a second QOI image next to it.
+*/
+
+use std::str::FromStr;
+use image::{DynamicImage, ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+
+use crate::quantize::BAYER_4X4;
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum DepthStrategy {
+    // Keep the high byte.
+    Truncate,
+    // Nearest 8 bit value, what the image crate does.
+    #[default]
+    Round,
+    // Ordered dithering of the dropped bits, hides banding in gradients.
+    Dither,
+}
+
+impl FromStr for DepthStrategy {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(DepthStrategy::Truncate),
+            "round" => Ok(DepthStrategy::Round),
+            "dither" => Ok(DepthStrategy::Dither),
+            _ => Err(QoiError::InvalidArgument(format!("unknown depth strategy '{}'", s))),
+        }
+    }
+}
+
+// Bits per channel of 'img'.
+pub fn bit_depth(img: &DynamicImage) -> u8 {
+    match img {
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) |
+        DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_) => 16,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => 32,
+        _ => 8,
+    }
+}
+
+pub fn is_high_depth(img: &DynamicImage) -> bool {
+    bit_depth(img) > 8
+}
+
+/*
+RGB pixels of 'img' brought down to 8 bits with 'strategy'.
+ */
+pub fn to_rgb8(img: &DynamicImage, strategy: DepthStrategy) -> Vec<u8> {
+    if !is_high_depth(img) || strategy == DepthStrategy::Round {
+        return img.to_rgb8().into_raw();
+    }
+
+    let wide: Vec<u16> = img.to_rgb16().into_raw();
+    let width: usize = img.width() as usize;
+
+    match strategy {
+        DepthStrategy::Truncate => wide.iter().map(|v| (v >> 8) as u8).collect(),
+        _ => wide
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let (x, y) = ((i / 3) % width, (i / 3) / width);
+
+                // Rounds up when the dropped fraction is above the pixel's threshold.
+                let scaled: u32 = *v as u32 * 255;
+                let threshold: u32 = (BAYER_4X4[y % 4][x % 4] as u32 * 2 + 1) * u16::MAX as u32 / 32;
+                let rounded: u32 = scaled / u16::MAX as u32 + (scaled % u16::MAX as u32 > threshold) as u32;
+                rounded.min(255) as u8
+            })
+            .collect(),
+    }
+}
+
+/*
+Splits the RGB pixels of 'img' into its high and low bytes, both laid out as RGB images.
+Lossless for 16 bit sources, floats are clamped to [0, 1] first.
+ */
+pub fn split_planes(img: &DynamicImage) -> (Vec<u8>, Vec<u8>) {
+    let wide: Vec<u16> = img.to_rgb16().into_raw();
+    let high: Vec<u8> = wide.iter().map(|v| (v >> 8) as u8).collect();
+    let low: Vec<u8> = wide.iter().map(|v| (v & 0xFF) as u8).collect();
+    (high, low)
+}
+
+/*
+Joins the planes written by the split plane mode back into a 16 bit image.
+ */
+pub fn join_planes(high: &QoiFile, low: &QoiFile) -> Result<DynamicImage, QoiError> {
+    if (high.width, high.height) != (low.width, low.height) {
+        return Err(QoiError::DimensionMismatch(format!(
+            "high plane is {}x{}, low plane is {}x{}", high.width, high.height, low.width, low.height
+        )));
+    }
+
+    let wide: Vec<u16> = high
+        .to_image()
+        .to_rgb8()
+        .iter()
+        .zip(low.to_image().to_rgb8().iter())
+        .map(|(h, l)| (*h as u16) << 8 | *l as u16)
+        .collect();
+    let img: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_raw(high.width, high.height, wide).unwrap();
+    Ok(DynamicImage::ImageRgb16(img))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::comp::{decode_bytes, encode_image};
+    use crate::colorspace::ColorSpace;
+
+    fn wide_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb16(ImageBuffer::from_fn(width, height, |x, y| Rgb([(x * 218) as u16, (y * 327) as u16, ((x * y * 97) % 65536) as u16])))
+    }
+
+    fn planes_through_qoi(img: &DynamicImage) -> (QoiFile, QoiFile) {
+        let (high, low) = split_planes(img);
+        let decode = |pixels: &[u8]| decode_bytes(&encode_image(pixels, 3, img.width(), img.height(), ColorSpace::Srgb), PathBuf::new()).unwrap();
+        (decode(&high), decode(&low))
+    }
+
+    #[test]
+    fn split_planes_round_trip_losslessly() {
+        let img: DynamicImage = wide_image(30, 20);
+        let (high, low) = planes_through_qoi(&img);
+        assert_eq!(join_planes(&high, &low).unwrap().to_rgb16().into_raw(), img.to_rgb16().into_raw());
+    }
+
+    #[test]
+    fn planes_of_different_sizes_are_rejected() {
+        let (high, _) = planes_through_qoi(&wide_image(30, 20));
+        let (_, low) = planes_through_qoi(&wide_image(20, 30));
+        assert!(matches!(join_planes(&high, &low), Err(QoiError::DimensionMismatch(_))));
+    }
+
+    #[test]
+    fn strategies_stay_within_one_step_of_the_source() {
+        let img: DynamicImage = wide_image(64, 32);
+        let wide: Vec<u16> = img.to_rgb16().into_raw();
+
+        for strategy in [DepthStrategy::Truncate, DepthStrategy::Round, DepthStrategy::Dither] {
+            let narrow: Vec<u8> = to_rgb8(&img, strategy);
+            assert_eq!(narrow.len(), wide.len());
+            for (n, w) in narrow.iter().zip(&wide) {
+                let exact: f64 = *w as f64 / 257.0;
+                assert!((*n as f64 - exact).abs() < 1.0, "{:?} gave {} for {}", strategy, n, w);
+            }
+        }
+        assert_eq!(to_rgb8(&img, DepthStrategy::Truncate), wide.iter().map(|v| (v >> 8) as u8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn depths_and_strategy_names() {
+        assert_eq!(bit_depth(&wide_image(1, 1)), 16);
+        assert_eq!(bit_depth(&DynamicImage::new_rgb32f(1, 1)), 32);
+        assert!(!is_high_depth(&DynamicImage::new_rgb8(1, 1)));
+
+        assert_eq!("dither".parse::<DepthStrategy>().ok(), Some(DepthStrategy::Dither));
+        assert!(matches!("floor".parse::<DepthStrategy>(), Err(QoiError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,239 @@
+/*
+Optional second compression stage wrapped around the QOI stream.
+Compressed files are recognised by their frame magic, so the decoder handles them transparently.
+*/
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+use crate::consts::*;
+use crate::qoi_errror::QoiError;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+
+// zstd's own default, a good balance for QOI streams.
+const ZSTD_LEVEL: i32 = 3;
+
+// Room left after the end mark for a trailer, far above what Qross writes.
+const MAX_TRAILER_SIZE: usize = 1 << 20;
+
+// Longest chunk, a QOI_OP_RGBA.
+const MAX_CHUNK_SIZE: usize = 5;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Entropy {
+    Zstd,
+    Lz4,
+}
+
+impl Entropy {
+
+    // Appended to the ".qoi" extension.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Entropy::Zstd => "zst",
+            Entropy::Lz4 => "lz4",
+        }
+    }
+
+    // Stage used by the file in 'bytes', None for a plain QOI stream.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Entropy::Zstd)
+        } else if bytes.starts_with(&LZ4_MAGIC) {
+            Some(Entropy::Lz4)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Entropy {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Entropy::Zstd),
+            "lz4" => Ok(Entropy::Lz4),
+            _ => Err(QoiError::InvalidArgument(format!("unknown entropy stage '{}'", s))),
+        }
+    }
+}
+
+/*
+Writer handed to the encoders, compresses everything written into it with the chosen stage.
+'finish' must be called to complete the frame.
+ */
+pub enum EntropyWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+    Lz4(FrameEncoder<W>),
+}
+
+impl<W: Write> EntropyWriter<W> {
+
+    pub fn new(writer: W, entropy: Option<Entropy>) -> Result<Self, io::Error> {
+        Ok(match entropy {
+            None => EntropyWriter::Plain(writer),
+            Some(Entropy::Zstd) => EntropyWriter::Zstd(zstd::Encoder::new(writer, ZSTD_LEVEL)?),
+            Some(Entropy::Lz4) => EntropyWriter::Lz4(FrameEncoder::new(writer)),
+        })
+    }
+
+    // Completes the frame and returns the flushed inner writer.
+    pub fn finish(self) -> Result<W, io::Error> {
+        let mut writer: W = match self {
+            EntropyWriter::Plain(writer) => writer,
+            EntropyWriter::Zstd(encoder) => encoder.finish()?,
+            EntropyWriter::Lz4(encoder) => encoder.finish().map_err(io::Error::from)?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for EntropyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EntropyWriter::Plain(writer) => writer.write(buf),
+            EntropyWriter::Zstd(encoder) => encoder.write(buf),
+            EntropyWriter::Lz4(encoder) => encoder.write(buf),
+        }
+    }
+
+    // Only the plain writer is flushed, flushing a frame early would cost ratio.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EntropyWriter::Plain(writer) => writer.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/*
+Decompresses 'reader' into 'out', failing once the stream outgrows what its QOI header allows,
+so a small crafted file can't expand without limit.
+ */
+fn read_bounded<R: Read>(mut reader: R, out: &mut Vec<u8>) -> io::Result<usize> {
+    reader.by_ref().take(QOI_HEADER_SIZE as u64).read_to_end(out)?;
+    if out.len() < QOI_HEADER_SIZE {
+        return Ok(out.len());
+    }
+
+    let width: u32 = u32::from_be_bytes([out[4], out[5], out[6], out[7]]);
+    let height: u32 = u32::from_be_bytes([out[8], out[9], out[10], out[11]]);
+    let pixel_count: u64 = width as u64 * height as u64;
+    if pixel_count > QOI_PIXELS_MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}x{} is above the {} pixel limit", width, height, QOI_PIXELS_MAX)));
+    }
+
+    let limit: usize = QOI_HEADER_SIZE + pixel_count as usize * MAX_CHUNK_SIZE + QOI_END_MARK_SIZE + MAX_TRAILER_SIZE;
+    reader.take((limit - out.len()) as u64 + 1).read_to_end(out)?;
+    if out.len() > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("stream outgrows the {} bytes a {}x{} image can take", limit, width, height)));
+    }
+    Ok(out.len())
+}
+
+/*
+Returns the QOI stream held by 'bytes', decompressing it when a second stage was used.
+ */
+pub fn unwrap(bytes: &[u8]) -> Result<Cow<'_, [u8]>, QoiError> {
+    let mut out: Vec<u8> = Vec::new();
+    match Entropy::detect(bytes) {
+        None => return Ok(Cow::Borrowed(bytes)),
+        Some(Entropy::Zstd) => read_bounded(zstd::Decoder::new(bytes)?, &mut out)?,
+        Some(Entropy::Lz4) => read_bounded(FrameDecoder::new(bytes), &mut out)?,
+    };
+    Ok(Cow::Owned(out))
+}
+
+/*
+Like 'unwrap', but a damaged second stage gives back what was decompressed before the error, with the error.
+ */
+pub fn unwrap_partial(bytes: &[u8]) -> (Cow<'_, [u8]>, Option<String>) {
+    let mut out: Vec<u8> = Vec::new();
+    let read: io::Result<usize> = match Entropy::detect(bytes) {
+        None => return (Cow::Borrowed(bytes), None),
+        Some(Entropy::Zstd) => zstd::Decoder::new(bytes).and_then(|decoder| read_bounded(decoder, &mut out)),
+        Some(Entropy::Lz4) => read_bounded(FrameDecoder::new(bytes), &mut out),
+    };
+    (Cow::Owned(out), read.err().map(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::comp::{decode_bytes, encode_image};
+    use crate::colorspace::ColorSpace;
+
+    fn stream() -> Vec<u8> {
+        let pixels: Vec<u8> = (0..64 * 64 * 3).map(|i| (i / 97 * 13) as u8).collect();
+        encode_image(&pixels, 3, 64, 64, ColorSpace::Srgb)
+    }
+
+    fn wrapped(entropy: Option<Entropy>, bytes: &[u8]) -> Vec<u8> {
+        let mut writer: EntropyWriter<Vec<u8>> = EntropyWriter::new(Vec::new(), entropy).unwrap();
+        writer.write_all(bytes).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn every_stage_round_trips() {
+        let stream: Vec<u8> = stream();
+        for entropy in [None, Some(Entropy::Zstd), Some(Entropy::Lz4)] {
+            let bytes: Vec<u8> = wrapped(entropy, &stream);
+            assert_eq!(Entropy::detect(&bytes), entropy);
+            assert_eq!(unwrap(&bytes).unwrap().as_ref(), stream.as_slice(), "{:?}", entropy);
+            assert_eq!(decode_bytes(&bytes, PathBuf::new()).unwrap().pixels, decode_bytes(&stream, PathBuf::new()).unwrap().pixels);
+        }
+    }
+
+    #[test]
+    fn damaged_stages_fail_or_keep_what_was_read() {
+        let stream: Vec<u8> = stream();
+        for entropy in [Entropy::Zstd, Entropy::Lz4] {
+            let bytes: Vec<u8> = wrapped(Some(entropy), &stream);
+            // Cut inside a block, LZ4 frames read fine without their end mark.
+            let cut: &[u8] = &bytes[..bytes.len() / 2];
+
+            assert!(unwrap(cut).is_err(), "{:?}", entropy);
+            let (partial, error) = unwrap_partial(cut);
+            assert!(error.is_some(), "{:?}", entropy);
+            assert!(stream.starts_with(&partial), "{:?}", entropy);
+        }
+    }
+
+    #[test]
+    fn expansion_is_bounded_by_the_header() {
+        // A 1x1 header followed by far more bytes than one pixel and a trailer can take.
+        let mut bomb: Vec<u8> = stream()[..QOI_HEADER_SIZE].to_vec();
+        bomb[4..12].copy_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+        bomb.resize(MAX_TRAILER_SIZE * 4, 0);
+
+        let mut huge: Vec<u8> = stream()[..QOI_HEADER_SIZE].to_vec();
+        huge[4..12].copy_from_slice(&[0xFF; 8]);
+
+        for entropy in [Entropy::Zstd, Entropy::Lz4] {
+            let bytes: Vec<u8> = wrapped(Some(entropy), &bomb);
+            assert!(unwrap(&bytes).is_err(), "{:?}", entropy);
+            let (partial, error) = unwrap_partial(&bytes);
+            assert!(error.unwrap().contains("outgrows"), "{:?}", entropy);
+            assert!(partial.len() <= QOI_HEADER_SIZE + MAX_CHUNK_SIZE + QOI_END_MARK_SIZE + MAX_TRAILER_SIZE + 1);
+
+            assert!(unwrap(&wrapped(Some(entropy), &huge)).is_err(), "{:?}", entropy);
+        }
+    }
+
+    #[test]
+    fn stage_names_parse() {
+        assert_eq!("zstd".parse::<Entropy>().ok(), Some(Entropy::Zstd));
+        assert_eq!("lz4".parse::<Entropy>().ok(), Some(Entropy::Lz4));
+        assert!(matches!("gzip".parse::<Entropy>(), Err(QoiError::InvalidArgument(_))));
+    }
+}
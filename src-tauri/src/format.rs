@@ -0,0 +1,235 @@
+/*
+Automatic choice of the output format: every image is encoded as QOI, QOI with the zstd stage and PNG,
+a policy picks the one written. Sizes and decode times of all of them end up in the report.
+*/
+
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+use image::{ColorType, ImageEncoder, ImageFormat};
+use image::codecs::png::PngEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::comp::{decode_bytes, encode_image};
+use crate::colorspace::ColorSpace;
+use crate::entropy::{Entropy, EntropyWriter};
+use crate::qoi_errror::QoiError;
+
+// Percent over the smallest output a faster format may take when no budget is given.
+const DEFAULT_BUDGET: f64 = 10.0;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Qoi,
+    QoiZstd,
+    Png,
+}
+
+impl OutputFormat {
+
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Qoi, OutputFormat::QoiZstd, OutputFormat::Png];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Qoi => "qoi",
+            OutputFormat::QoiZstd => "qoi+zstd",
+            OutputFormat::Png => "png",
+        }
+    }
+
+    // Extension of the written file, the same ones compress uses without auto format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Qoi => "qoi",
+            OutputFormat::QoiZstd => "qoi.zst",
+            OutputFormat::Png => "png",
+        }
+    }
+
+    // Second stage of the QOI formats.
+    pub fn entropy(&self) -> Option<Entropy> {
+        match self {
+            OutputFormat::QoiZstd => Some(Entropy::Zstd),
+            _ => None,
+        }
+    }
+
+    /*
+    Encodes 'pixels' ('channels' bytes per pixel) into a complete file of this format.
+    PNG has no colorspace flag, the pixels are stored as given.
+     */
+    pub fn encode(&self, pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace) -> Result<Vec<u8>, QoiError> {
+        match self {
+            OutputFormat::Qoi => Ok(encode_image(pixels, channels, width, height, color_space)),
+            OutputFormat::QoiZstd => {
+                let mut writer: EntropyWriter<Vec<u8>> = EntropyWriter::new(Vec::new(), Some(Entropy::Zstd))?;
+                writer.write_all(&encode_image(pixels, channels, width, height, color_space))?;
+                Ok(writer.finish()?)
+            }
+            OutputFormat::Png => {
+                let color_type: ColorType = match channels {
+                    1 => ColorType::L8,
+                    2 => ColorType::La8,
+                    4 => ColorType::Rgba8,
+                    _ => ColorType::Rgb8,
+                };
+                let mut out: Vec<u8> = Vec::new();
+                PngEncoder::new(&mut out).write_image(pixels, width, height, color_type)?;
+                Ok(out)
+            }
+        }
+    }
+
+    // Decodes a file written by 'encode', only used to time it.
+    fn decode(&self, bytes: &[u8]) -> Result<(), QoiError> {
+        match self {
+            OutputFormat::Qoi | OutputFormat::QoiZstd => decode_bytes(bytes, PathBuf::new()).map(|_| ()),
+            OutputFormat::Png => image::load_from_memory_with_format(bytes, ImageFormat::Png).map(|_| ()).map_err(QoiError::from),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum FormatPolicy {
+    Smallest,
+    // Fastest to decode among the formats at most 'budget' percent larger than the smallest.
+    FastestDecode { budget: f64 },
+}
+
+impl FromStr for FormatPolicy {
+    type Err = QoiError;
+
+    // "smallest", "fastest-decode" or "fastest-decode=<percent>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').unwrap_or((s, ""));
+        match (name, value) {
+            ("smallest", "") => Ok(FormatPolicy::Smallest),
+            ("fastest-decode", "") => Ok(FormatPolicy::FastestDecode { budget: DEFAULT_BUDGET }),
+            ("fastest-decode", budget) => match budget.parse::<f64>() {
+                Ok(budget) if budget >= 0.0 => Ok(FormatPolicy::FastestDecode { budget }),
+                _ => Err(QoiError::InvalidArgument(format!("invalid size budget '{}', expected a percent", budget))),
+            },
+            _ => Err(QoiError::InvalidArgument(format!("unknown format policy '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for FormatPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatPolicy::Smallest => write!(f, "smallest"),
+            FormatPolicy::FastestDecode { budget } => write!(f, "fastest decode within {}% of the smallest", budget),
+        }
+    }
+}
+
+// One format tried for an image.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Candidate {
+    pub format: OutputFormat,
+    pub size: usize,
+    pub decode_ms: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FormatChoice {
+    pub policy: FormatPolicy,
+    pub chosen: OutputFormat,
+    pub candidates: Vec<Candidate>,
+}
+
+/*
+Encodes and decodes 'pixels' in every output format and picks one with 'policy'.
+ */
+pub fn choose(policy: FormatPolicy, pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace) -> Result<FormatChoice, QoiError> {
+    let candidates: Vec<Candidate> = OutputFormat::ALL
+        .iter()
+        .map(|format| {
+            let encoded: Vec<u8> = format.encode(pixels, channels, width, height, color_space)?;
+            let start: Instant = Instant::now();
+            format.decode(&encoded)?;
+            Ok(Candidate { format: *format, size: encoded.len(), decode_ms: start.elapsed().as_secs_f64() * 1000.0 })
+        })
+        .collect::<Result<Vec<Candidate>, QoiError>>()?;
+
+    let smallest: &Candidate = candidates.iter().min_by_key(|c| c.size).expect("there is always a candidate");
+    let chosen: OutputFormat = match policy {
+        FormatPolicy::Smallest => smallest.format,
+        FormatPolicy::FastestDecode { budget } => {
+            let limit: f64 = smallest.size as f64 * (1.0 + budget / 100.0);
+            candidates
+                .iter()
+                .filter(|c| c.size as f64 <= limit)
+                .min_by(|a, b| a.decode_ms.total_cmp(&b.decode_ms))
+                .map_or(smallest.format, |c| c.format)
+        }
+    };
+
+    Ok(FormatChoice { policy, chosen, candidates })
+}
+
+impl fmt::Display for FormatChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} chosen ({})", self.chosen.name(), self.policy)?;
+        for candidate in &self.candidates {
+            write!(f, "\n    {:<9} {:>12} bytes {:>9.2} ms decode", candidate.format.name(), candidate.size, candidate.decode_ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::fixtures;
+
+    fn sample() -> Vec<u8> {
+        fixtures::sample(48, 32, 3)
+    }
+
+    #[test]
+    fn policies_parse() {
+        assert_eq!("smallest".parse::<FormatPolicy>().unwrap(), FormatPolicy::Smallest);
+        assert_eq!("fastest-decode".parse::<FormatPolicy>().unwrap(), FormatPolicy::FastestDecode { budget: DEFAULT_BUDGET });
+        assert_eq!("fastest-decode=2.5".parse::<FormatPolicy>().unwrap(), FormatPolicy::FastestDecode { budget: 2.5 });
+
+        for invalid in ["fastest", "smallest=3", "fastest-decode=-1", "fastest-decode=many"] {
+            assert!(matches!(invalid.parse::<FormatPolicy>(), Err(QoiError::InvalidArgument(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let pixels: Vec<u8> = sample();
+        for format in OutputFormat::ALL {
+            let encoded: Vec<u8> = format.encode(&pixels, 3, 48, 32, ColorSpace::Srgb).unwrap();
+            let decoded: Vec<u8> = match format {
+                OutputFormat::Png => image::load_from_memory_with_format(&encoded, ImageFormat::Png).unwrap().into_rgb8().into_raw(),
+                _ => decode_bytes(&encoded, PathBuf::new()).unwrap().pixels,
+            };
+            assert_eq!(decoded, pixels, "{}", format.name());
+            assert_eq!(Entropy::detect(&encoded), format.entropy());
+        }
+    }
+
+    #[test]
+    fn policies_pick_from_the_candidates() {
+        let pixels: Vec<u8> = sample();
+
+        let choice: FormatChoice = choose(FormatPolicy::Smallest, &pixels, 3, 48, 32, ColorSpace::Srgb).unwrap();
+        let smallest: usize = choice.candidates.iter().map(|c| c.size).min().unwrap();
+        assert_eq!(choice.candidates.len(), OutputFormat::ALL.len());
+        assert_eq!(choice.candidates.iter().find(|c| c.format == choice.chosen).unwrap().size, smallest);
+
+        // Without a budget only the smallest sizes qualify, with a huge one the fastest decode wins.
+        let choice: FormatChoice = choose(FormatPolicy::FastestDecode { budget: 0.0 }, &pixels, 3, 48, 32, ColorSpace::Srgb).unwrap();
+        let smallest: usize = choice.candidates.iter().map(|c| c.size).min().unwrap();
+        assert_eq!(choice.candidates.iter().find(|c| c.format == choice.chosen).unwrap().size, smallest);
+
+        let choice: FormatChoice = choose(FormatPolicy::FastestDecode { budget: 1e9 }, &pixels, 3, 48, 32, ColorSpace::Srgb).unwrap();
+        let fastest: &Candidate = choice.candidates.iter().min_by(|a, b| a.decode_ms.total_cmp(&b.decode_ms)).unwrap();
+        assert_eq!(choice.chosen, fastest.format);
+    }
+}
@@ -0,0 +1,407 @@
+/*
+QOI stream inspector: walks a file chunk by chunk and lists what each one decodes to,
+so a bad output can be traced to the chunk that went wrong instead of the end mark.
+Chunks a canonical encoder wouldn't have written are flagged.
+*/
+
+use std::borrow::Cow;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::consts::*;
+use crate::entropy;
+use crate::pixel::{Pixel, Zero};
+use crate::qoi_errror::QoiError;
+
+const MAX_RUN: usize = 62;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Rgb,
+    Rgba,
+    Index,
+    Diff,
+    Luma,
+    Run,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = match self {
+            Op::Rgb => "RGB",
+            Op::Rgba => "RGBA",
+            Op::Index => "INDEX",
+            Op::Diff => "DIFF",
+            Op::Luma => "LUMA",
+            Op::Run => "RUN",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Which chunks are listed, every chunk is still decoded to keep the state right.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct InspectOptions {
+    // First pixel of the listed range.
+    pub from: usize,
+    // Pixel after the listed range, None lists up to the end.
+    pub to: Option<usize>,
+    // Copy the whole index table after every listed chunk.
+    pub index_table: bool,
+    // Only list non-canonical chunks.
+    pub non_canonical_only: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Chunk {
+    pub offset: usize,
+    pub op: Op,
+    // Operands of the chunk as written.
+    pub values: String,
+    // First pixel the chunk decodes to, with its coordinate.
+    pub pixel: usize,
+    pub x: u32,
+    pub y: u32,
+    // Amount of pixels, above 1 only for runs.
+    pub length: usize,
+    // Resulting RGBA color.
+    pub color: [u8; 4],
+    // Index slot the color is stored in.
+    pub slot: usize,
+    // Why the chunk isn't canonical.
+    pub note: Option<String>,
+    pub index: Option<Vec<[u8; 4]>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Inspection {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub color_space: u8,
+    // Totals over the whole stream, not only the listed chunks.
+    pub chunk_count: usize,
+    pub non_canonical: usize,
+    pub end_mark: bool,
+    pub trailer: bool,
+    // Where the walk had to stop, None when every pixel was decoded.
+    pub error: Option<String>,
+    pub chunks: Vec<Chunk>,
+}
+
+fn rgba(pixel: &Pixel) -> [u8; 4] {
+    [pixel.r, pixel.g, pixel.b, pixel.a]
+}
+
+/*
+Chunk a canonical encoder writes for 'pixel' after 'prev', None for a run.
+ */
+fn canonical_op(pixel: &Pixel, prev: &Pixel, seen: &[Pixel; 64]) -> Option<Op> {
+    if pixel == prev {
+        return None;
+    }
+    if seen[pixel.hash() % seen.len()] == *pixel {
+        return Some(Op::Index);
+    }
+    if pixel.a != prev.a {
+        return Some(Op::Rgba);
+    }
+
+    let dr: i16 = pixel.r.wrapping_sub(prev.r) as i8 as i16;
+    let dg: i16 = pixel.g.wrapping_sub(prev.g) as i8 as i16;
+    let db: i16 = pixel.b.wrapping_sub(prev.b) as i8 as i16;
+    let (dr_dg, db_dg) = (dr - dg, db - dg);
+
+    if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+        Some(Op::Diff)
+    } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+        Some(Op::Luma)
+    } else {
+        Some(Op::Rgb)
+    }
+}
+
+/*
+Walks the QOI stream in 'bytes', zstd and LZ4 compressed streams are unwrapped first.
+A stream ending early or holding garbage is reported in 'error' with everything read before it.
+ */
+pub fn inspect(bytes: &[u8], options: &InspectOptions) -> Result<Inspection, QoiError> {
+    let bytes: Cow<[u8]> = entropy::unwrap(bytes)?;
+    if bytes.len() < QOI_HEADER_SIZE || bytes[0..4] != QOI_MAGIC {
+        return Err(QoiError::InvalidHeader(format!("{:?}", &bytes[..bytes.len().min(4)])));
+    }
+
+    let width: u32 = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let height: u32 = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let pixel_count: usize = width as usize * height as usize;
+    let to: usize = options.to.unwrap_or(pixel_count);
+
+    let mut inspection: Inspection = Inspection {
+        width,
+        height,
+        channels: bytes[12],
+        color_space: bytes[13],
+        chunk_count: 0,
+        non_canonical: 0,
+        end_mark: false,
+        trailer: false,
+        error: None,
+        chunks: Vec::new(),
+    };
+
+    let mut pos: usize = QOI_HEADER_SIZE;
+    let mut pixel: usize = 0;
+    let mut prev: Pixel = Pixel::zero();
+    let mut seen: [Pixel; 64] = [Pixel::INDEX_START; 64];
+    let mut last_run: Option<usize> = None;
+
+    while pixel < pixel_count {
+        let offset: usize = pos;
+        let Some(&byte) = bytes.get(pos) else {
+            inspection.error = Some(format!("stream ends at byte {} after {} of {} pixels", pos, pixel, pixel_count));
+            break;
+        };
+
+        // Size of the chunk, operands included.
+        let size: usize = match byte {
+            QOI_OP_RGB => 4,
+            QOI_OP_RGBA => 5,
+            _ if byte & QOI_2BIT_TAG_MASK == QOI_OP_LUMA => 2,
+            _ => 1,
+        };
+        let Some(chunk) = bytes.get(pos..pos + size) else {
+            inspection.error = Some(format!("chunk at byte {} is cut off by the end of the stream", pos));
+            break;
+        };
+
+        let mut current: Pixel = prev;
+        let mut length: usize = 1;
+        let (op, values): (Op, String) = match byte {
+            QOI_OP_RGB => {
+                (current.r, current.g, current.b) = (chunk[1], chunk[2], chunk[3]);
+                (Op::Rgb, format!("{} {} {}", chunk[1], chunk[2], chunk[3]))
+            }
+            QOI_OP_RGBA => {
+                (current.r, current.g, current.b, current.a) = (chunk[1], chunk[2], chunk[3], chunk[4]);
+                (Op::Rgba, format!("{} {} {} {}", chunk[1], chunk[2], chunk[3], chunk[4]))
+            }
+            _ => match byte & QOI_2BIT_TAG_MASK {
+                QOI_OP_INDEX => {
+                    let slot: usize = (byte & QOI_INDEX_VALUE_MASK) as usize;
+                    current = seen[slot];
+                    (Op::Index, format!("slot {}", slot))
+                }
+                QOI_OP_DIFF => {
+                    let dr: i16 = ((byte & QOI_RED_DIFF) >> 4) as i16 - 2;
+                    let dg: i16 = ((byte & QOI_GREEN_DIFF) >> 2) as i16 - 2;
+                    let db: i16 = (byte & QOI_BLUE_DIFF) as i16 - 2;
+                    current.r = current.r.wrapping_add(dr as u8);
+                    current.g = current.g.wrapping_add(dg as u8);
+                    current.b = current.b.wrapping_add(db as u8);
+                    (Op::Diff, format!("{:+} {:+} {:+}", dr, dg, db))
+                }
+                QOI_OP_LUMA => {
+                    let dg: i16 = (byte & QOI_LUMA_DG) as i16 - 32;
+                    let dr_dg: i16 = ((chunk[1] & QOI_LUMA_DRDG_MASK) >> 4) as i16 - 8;
+                    let db_dg: i16 = (chunk[1] & QOI_LUMA_DBDG_MASK) as i16 - 8;
+                    current.r = current.r.wrapping_add((dg + dr_dg) as u8);
+                    current.g = current.g.wrapping_add(dg as u8);
+                    current.b = current.b.wrapping_add((dg + db_dg) as u8);
+                    (Op::Luma, format!("{:+} {:+} {:+}", dg, dr_dg, db_dg))
+                }
+                _ => {
+                    length = (byte & QOI_RUN_LENGTH_MASK) as usize + 1;
+                    (Op::Run, format!("length {}", length))
+                }
+            },
+        };
+
+        // Compared against what a canonical encoder would have written at this point.
+        let note: Option<String> = match (op, canonical_op(&current, &prev, &seen)) {
+            (Op::Run, _) if last_run.is_some_and(|run| run < MAX_RUN) => {
+                Some("run continues a run shorter than 62".to_string())
+            }
+            (Op::Run, _) => None,
+            (_, None) => Some("repeats the previous pixel, a run is shorter".to_string()),
+            (op, Some(canonical)) if op != canonical => Some(format!("{} is shorter", canonical)),
+            _ => None,
+        };
+        if pixel + length > pixel_count {
+            inspection.error = Some(format!("run at byte {} goes {} pixels past the image", pos, pixel + length - pixel_count));
+        }
+
+        let slot: usize = current.hash() % seen.len();
+        seen[slot] = current;
+        inspection.chunk_count += 1;
+        inspection.non_canonical += note.is_some() as usize;
+
+        let listed: bool = pixel < to && pixel + length > options.from && (note.is_some() || !options.non_canonical_only);
+        if listed {
+            inspection.chunks.push(Chunk {
+                offset,
+                op,
+                values,
+                pixel,
+                x: (pixel % width as usize) as u32,
+                y: (pixel / width as usize) as u32,
+                length,
+                color: rgba(&current),
+                slot,
+                note,
+                index: options.index_table.then(|| seen.iter().map(rgba).collect()),
+            });
+        }
+
+        last_run = (op == Op::Run).then_some(length);
+        prev = current;
+        pos += size;
+        pixel += length;
+    }
+
+    inspection.end_mark = bytes.get(pos..pos + QOI_END_MARK_SIZE) == Some(&QOI_END_MARK[..]);
+    if inspection.end_mark {
+        inspection.trailer = bytes[pos + QOI_END_MARK_SIZE..].starts_with(&QOI_TRAILER_MAGIC);
+    } else if inspection.error.is_none() {
+        let found: &[u8] = &bytes[pos.min(bytes.len())..(pos + QOI_END_MARK_SIZE).min(bytes.len())];
+        inspection.error = Some(format!("expected the end mark at byte {}, found {:?}", pos, found));
+    }
+
+    Ok(inspection)
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.color;
+        write!(
+            f, "{} {:>10}  {:<5} {:<12} px {:>9} ({}, {}) -> rgba({}, {}, {}, {}) slot {}",
+            if self.note.is_some() { "!" } else { " " },
+            self.offset, self.op.to_string(), self.values, self.pixel, self.x, self.y, r, g, b, a, self.slot
+        )?;
+        if let Some(note) = &self.note {
+            write!(f, "  <- {}", note)?;
+        }
+        if let Some(index) = &self.index {
+            let used: Vec<String> = index
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c != rgba(&Pixel::INDEX_START))
+                .map(|(slot, [r, g, b, a])| format!("{}:{},{},{},{}", slot, r, g, b, a))
+                .collect();
+            write!(f, "\n{:>14}index [{}]", "", used.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}x{}, {} channels, colorspace {}", self.width, self.height, self.channels, self.color_space)?;
+        for chunk in &self.chunks {
+            writeln!(f, "{}", chunk)?;
+        }
+        write!(f, "{} chunks, {} non-canonical", self.chunk_count, self.non_canonical)?;
+        write!(f, ", end mark {}", if self.end_mark { "found" } else { "missing" })?;
+        if self.trailer {
+            write!(f, ", Qross trailer")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, "\nerror: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::colorspace::ColorSpace;
+    use crate::comp::encode_image;
+    use crate::comp::fixtures::sample;
+    use crate::entropy::{Entropy, EntropyWriter};
+
+    // 4x1 RGB stream: a canonical RGB, an RGB a DIFF would cover, a run and a second short run.
+    fn handmade() -> Vec<u8> {
+        let mut bytes: Vec<u8> = QOI_MAGIC.to_vec();
+        bytes.extend(4u32.to_be_bytes());
+        bytes.extend(1u32.to_be_bytes());
+        bytes.extend([3, QOI_SRGB]);
+        bytes.extend([QOI_OP_RGB, 10, 20, 30]);
+        bytes.extend([QOI_OP_RGB, 11, 21, 31]);
+        bytes.extend([QOI_OP_RUN, QOI_OP_RUN]);
+        bytes.extend(QOI_END_MARK);
+        bytes
+    }
+
+    #[test]
+    fn canonical_stream_has_no_notes() {
+        // The sample stays below 128 at this size, so no delta wraps, the encoder doesn't use wrapped deltas.
+        let bytes: Vec<u8> = encode_image(&sample(24, 9, 4), 4, 24, 9, ColorSpace::Srgb);
+        let inspection: Inspection = inspect(&bytes, &InspectOptions::default()).unwrap();
+
+        assert_eq!((inspection.width, inspection.height, inspection.channels), (24, 9, CHANNELS));
+        assert_eq!(inspection.non_canonical, 0);
+        assert_eq!(inspection.chunks.len(), inspection.chunk_count);
+        assert_eq!(inspection.chunks.iter().map(|c| c.length).sum::<usize>(), 24 * 9);
+        assert!(inspection.end_mark && !inspection.trailer && inspection.error.is_none());
+    }
+
+    #[test]
+    fn non_canonical_chunks_are_flagged() {
+        let inspection: Inspection = inspect(&handmade(), &InspectOptions::default()).unwrap();
+        let notes: Vec<Option<&str>> = inspection.chunks.iter().map(|c| c.note.as_deref()).collect();
+
+        assert_eq!(notes, [None, Some("DIFF is shorter"), None, Some("run continues a run shorter than 62")]);
+        assert_eq!(inspection.non_canonical, 2);
+        assert_eq!(inspection.chunks[3].color, [11, 21, 31, 255]);
+        assert_eq!((inspection.chunks[3].pixel, inspection.chunks[3].x), (3, 3));
+    }
+
+    #[test]
+    fn range_and_filter_limit_the_listing() {
+        let options: InspectOptions = InspectOptions { from: 1, to: Some(3), ..Default::default() };
+        let inspection: Inspection = inspect(&handmade(), &options).unwrap();
+        assert_eq!(inspection.chunks.iter().map(|c| c.pixel).collect::<Vec<usize>>(), [1, 2]);
+        assert_eq!(inspection.chunk_count, 4);
+
+        let options: InspectOptions = InspectOptions { non_canonical_only: true, index_table: true, ..Default::default() };
+        let inspection: Inspection = inspect(&handmade(), &options).unwrap();
+        assert_eq!(inspection.chunks.iter().map(|c| c.offset).collect::<Vec<usize>>(), [18, 23]);
+        assert!(inspection.chunks.iter().all(|c| c.index.as_ref().is_some_and(|index| index.len() == 64)));
+    }
+
+    #[test]
+    fn damaged_streams_report_where_they_stop() {
+        let bytes: Vec<u8> = handmade();
+
+        let inspection: Inspection = inspect(&bytes[..20], &InspectOptions::default()).unwrap();
+        assert_eq!(inspection.chunks.len(), 1);
+        assert!(!inspection.end_mark);
+        assert!(inspection.error.unwrap().contains("cut off"));
+
+        let inspection: Inspection = inspect(&bytes[..bytes.len() - 3], &InspectOptions::default()).unwrap();
+        assert_eq!(inspection.chunk_count, 4);
+        assert!(inspection.error.unwrap().contains("end mark"));
+
+        let mut garbage: Vec<u8> = bytes.clone();
+        garbage[0] = b'x';
+        assert!(matches!(inspect(&garbage, &InspectOptions::default()), Err(QoiError::InvalidHeader(_))));
+        assert!(matches!(inspect(&bytes[..8], &InspectOptions::default()), Err(QoiError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn trailer_and_entropy_stage_are_seen_through() {
+        let mut bytes: Vec<u8> = handmade();
+        bytes.extend(QOI_TRAILER_MAGIC);
+        bytes.extend([0; 8]);
+
+        let mut writer: EntropyWriter<Vec<u8>> = EntropyWriter::new(Vec::new(), Some(Entropy::Zstd)).unwrap();
+        writer.write_all(&bytes).unwrap();
+        let wrapped: Vec<u8> = writer.finish().unwrap();
+
+        let inspection: Inspection = inspect(&wrapped, &InspectOptions::default()).unwrap();
+        assert!(inspection.end_mark && inspection.trailer);
+        assert_eq!(inspection.non_canonical, 2);
+    }
+}
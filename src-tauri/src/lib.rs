@@ -0,0 +1,30 @@
+pub mod db;
+pub mod consts;
+pub mod pixel;
+pub mod comp;
+pub mod qoi_errror;
+pub mod qoi_file;
+pub mod options;
+pub mod quantize;
+pub mod metrics;
+pub mod report;
+pub mod cli;
+pub mod stripes;
+pub mod simd;
+pub mod colorspace;
+pub mod metadata;
+pub mod container;
+pub mod entropy;
+pub mod tiles;
+pub mod sequence;
+pub mod depth;
+pub mod transform;
+pub mod preset;
+pub mod optimize;
+pub mod codec;
+pub mod stats;
+pub mod inspect;
+pub mod salvage;
+pub mod bench;
+pub mod compare;
+pub mod format;
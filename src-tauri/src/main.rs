@@ -1,28 +1,96 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-pub mod db;
-pub mod consts;
-pub mod pixel;
-pub mod comp;
-pub mod qoi_errror;
-pub mod qoi_file;
-
 use std::env;
 use std::fs;
-use comp::Package;
 use tauri::State;
 use std::path::Path;
 use rusqlite::Error;
 use std::ffi::OsStr;
 
-use crate::consts::IMG_FOLDER_PATH;
-use crate::db::{Table, DbFunctions};
+use image_compressor::{cli, metrics};
+use itertools::Itertools;
+use image_compressor::comp::{FileError, Package};
+use image_compressor::options::CompressOptions;
+use image_compressor::qoi_file::QoiFile;
+use image_compressor::sequence::Sequence;
+use image_compressor::colorspace::ColorSpace;
+use image_compressor::consts::IMG_FOLDER_PATH;
+use image_compressor::report::CompressionReport;
+use image_compressor::db::{Table, DbFunctions, FormatFunctions, MetadataFunctions, PresetFunctions};
+use image_compressor::preset::{self, Preset};
+use image_compressor::inspect::{self, InspectOptions};
+use image_compressor::salvage::{parse_color, Salvaged, DEFAULT_FILL};
+use image_compressor::compare::Comparison;
 
 fn create_img_folder() -> Result<(), std::io::Error>{
     fs::create_dir_all(IMG_FOLDER_PATH)?;
     Ok(())
 }
 
+fn metadata_table() -> Table {
+    let table_name: String = String::from("metadata");
+    Table {
+        create_query: format!("CREATE TABLE IF NOT EXISTS {} (
+            file_path TEXT,
+            metadata TEXT
+        )", table_name),
+        table_name,
+    }
+}
+
+fn presets_table() -> Table {
+    let table_name: String = String::from("presets");
+    Table {
+        create_query: format!("CREATE TABLE IF NOT EXISTS {} (
+            name TEXT PRIMARY KEY,
+            options TEXT
+        )", table_name),
+        table_name,
+    }
+}
+
+fn formats_table() -> Table {
+    let table_name: String = String::from("formats");
+    Table {
+        create_query: format!("CREATE TABLE IF NOT EXISTS {} (
+            file_path TEXT,
+            format TEXT,
+            encoded_path TEXT
+        )", table_name),
+        table_name,
+    }
+}
+
+fn fetch_presets() -> Option<Vec<Preset>> {
+    presets_table()
+        .fetch_all_presets()
+        .ok()?
+        .into_iter()
+        .map(|(name, options)| Preset::from_row(name, &options).ok())
+        .collect()
+}
+
+// Keeps the sidecars of compressed files inside the DB too.
+fn save_metadata(reports: &[CompressionReport]) {
+    let table: Table = metadata_table();
+    for report in reports {
+        let json: Option<String> = report.sidecar.as_ref().and_then(|s| fs::read_to_string(s).ok());
+        if let Some(json) = json {
+            table.save_metadata(&report.path, &json).ok();
+        }
+    }
+}
+
+// Records the output format auto format picked for every file.
+fn save_formats(reports: &[CompressionReport]) {
+    let table: Table = formats_table();
+    for report in reports {
+        if let Some(format) = &report.format {
+            table.save_format(&report.path, format.chosen.name(), &report.encoded_path).ok();
+        }
+    }
+}
+
 fn file_name(file: &str) -> &OsStr {
     Path::new(file)
         .file_name()
@@ -45,21 +113,142 @@ fn save_file_inside_db(app_db: State<'_, Table>, file: &str) -> Option<String> {
     Some(final_path)
 }
 
-#[tauri::command] 
-fn compress(app_db: State<'_, Table>) -> Option<String> {
+// Path and message of every failed file, for the frontend.
+fn failures_json(failures: &[FileError]) -> Vec<serde_json::Value> {
+    failures
+        .iter()
+        .map(|f| serde_json::json!({ "path": f.path, "error": f.error.to_string() }))
+        .collect()
+}
+
+// Compresses every file of the DB with 'options', returns the reports and the files that failed as JSON.
+fn compress_files(app_db: &Table, options: CompressOptions) -> Option<String> {
     let files: Result<Vec<String>, Error> = app_db.fetch_all_files();
     if let Ok(files) = files { 
-        let mut pack: Package = Package::with_files(files);
-        pack.compress_all();
-        Some("Success".to_string())
+        let pack: Package = Package::with_options(files, options);
+        let (reports, failures): (Vec<CompressionReport>, Vec<FileError>) = pack.compress_all().into_iter().partition_result();
+        save_metadata(&reports);
+        save_formats(&reports);
+        serde_json::to_string(&serde_json::json!({ "reports": reports, "failures": failures_json(&failures) })).ok()
     }
     else {
         Some("Failure".to_string())
     }
 }
 
+#[tauri::command] 
+fn compress(app_db: State<'_, Table>, options: Option<CompressOptions>) -> Option<String> {
+    compress_files(&app_db, options.unwrap_or_default())
+}
+
+#[tauri::command]
+fn create_preset(name: &str, options: CompressOptions) -> Option<String> {
+    let preset: Preset = Preset { name: name.to_string(), options };
+    presets_table().insert_preset(name, &preset.options_json()).ok()?;
+    Some(name.to_string())
+}
+
+#[tauri::command]
+fn edit_preset(name: &str, options: CompressOptions) -> Option<String> {
+    let preset: Preset = Preset { name: name.to_string(), options };
+    presets_table().update_preset(name, &preset.options_json()).ok()?.then(|| name.to_string())
+}
+
+#[tauri::command]
+fn delete_preset(name: &str) -> Option<String> {
+    presets_table().delete_preset(name).ok()?.then(|| name.to_string())
+}
+
+#[tauri::command]
+fn list_presets() -> Option<String> {
+    serde_json::to_string(&fetch_presets()?).ok()
+}
+
+#[tauri::command]
+fn apply_preset(app_db: State<'_, Table>, name: &str) -> Option<String> {
+    let options: String = presets_table().fetch_preset(name).ok()??;
+    let preset: Preset = Preset::from_row(name.to_string(), &options).ok()?;
+    compress_files(&app_db, preset.options)
+}
+
+#[tauri::command]
+fn export_presets(path: &str) -> Option<String> {
+    preset::export(&fetch_presets()?, Path::new(path)).ok()?;
+    Some(path.to_string())
+}
+
+// Presets with an existing name are replaced, returns the imported names.
+#[tauri::command]
+fn import_presets(path: &str) -> Option<String> {
+    let presets: Vec<Preset> = preset::import(Path::new(path)).ok()?;
+    let table: Table = presets_table();
+    for preset in &presets {
+        table.save_preset(&preset.name, &preset.options_json()).ok()?;
+    }
+    serde_json::to_string(&presets.iter().map(|p| &p.name).collect::<Vec<&String>>()).ok()
+}
+
+#[tauri::command]
+fn metrics(source: &str, output: &str) -> Option<String> {
+    let source = image::open(Path::new(source)).ok()?;
+    let output: QoiFile = QoiFile::open(Path::new(output)).ok()?;
+    let report = metrics::compare(&source, &output).ok()?;
+    serde_json::to_string(&report).ok()
+}
+
+// Every queued file encoded as QOI, PNG and lossless WebP, sizes and times and the files that failed as JSON.
+#[tauri::command]
+fn compare(app_db: State<'_, Table>, iterations: Option<usize>) -> Option<String> {
+    let files: Vec<String> = app_db.fetch_all_files().ok()?;
+    let (comparisons, failures): (Vec<Comparison>, Vec<FileError>) = Comparison::run_all(&files, iterations.unwrap_or(1))
+        .into_iter()
+        .partition_result();
+    serde_json::to_string(&serde_json::json!({ "comparisons": comparisons, "failures": failures_json(&failures) })).ok()
+}
+
+// Chunk listing of the QOI file, as JSON.
+#[tauri::command]
+fn inspect(file: &str, options: Option<InspectOptions>) -> Option<String> {
+    let bytes: Vec<u8> = fs::read(file).ok()?;
+    let inspection = inspect::inspect(&bytes, &options.unwrap_or_default()).ok()?;
+    serde_json::to_string(&inspection).ok()
+}
+
+// Recovers what it can of a damaged QOI file and writes it to 'output' as a valid one.
+#[tauri::command]
+fn salvage(file: &str, output: &str, fill: Option<&str>) -> Option<String> {
+    let fill: [u8; 3] = fill.map(parse_color).transpose().ok()?.unwrap_or(DEFAULT_FILL);
+    let salvaged: Salvaged = Salvaged::open(Path::new(file), fill).ok()?;
+    salvaged.save(Path::new(output)).ok()?;
+    serde_json::to_string(&serde_json::json!({
+        "recovered": salvaged.recovered,
+        "total": salvaged.file.width as usize * salvaged.file.height as usize,
+        "corruption": salvaged.corruption,
+    })).ok()
+}
+
+#[tauri::command]
+fn export(file: &str, output: &str, colorspace: Option<ColorSpace>) -> Option<String> {
+    let colorspace: ColorSpace = colorspace.unwrap_or(ColorSpace::Srgb);
+    if Sequence::is_sequence(Path::new(file)) {
+        Sequence::open(Path::new(file)).ok()?.export(Path::new(output), colorspace).ok()?;
+        return Some(output.to_string());
+    }
+
+    let qoi_file: QoiFile = QoiFile::open(Path::new(file)).ok()?;
+    qoi_file.export(Path::new(output), colorspace).ok()?;
+    Some(output.to_string())
+}
+
 fn main() -> Result<(), Error> {
     env::set_var("RUST_BACKTRACE", "1");
+
+    // Arguments mean the command line interface is used instead of the GUI.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(args));
+    }
+
     let app_db: Table;
     {
         // Different scope, for 'temp_table_name' to be dropped
@@ -76,6 +265,9 @@ fn main() -> Result<(), Error> {
         Ok(()) => Ok(()),
         _ => panic!("Opening table has raised an error!")
     }; 
+    metadata_table().create_table().expect("Opening metadata table has raised an error!");
+    presets_table().create_table().expect("Opening presets table has raised an error!");
+    formats_table().create_table().expect("Opening formats table has raised an error!");
     let _res_of_dir: Result<(), std::io::Error> = match create_img_folder() {
         Ok(()) => Ok(()),
         _ => panic!("Opening Image folder has raised an error!")
@@ -84,7 +276,10 @@ fn main() -> Result<(), Error> {
     // Boot the application.
     tauri::Builder::default()
     .manage(app_db)
-    .invoke_handler(tauri::generate_handler![save_file_inside_db, compress])
+    .invoke_handler(tauri::generate_handler![
+        save_file_inside_db, compress, metrics, compare, inspect, salvage, export,
+        create_preset, edit_preset, delete_preset, list_presets, apply_preset, export_presets, import_presets
+    ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
     Ok(())
@@ -0,0 +1,395 @@
+/*
+ICC profile and EXIF preservation.
+QOI has nowhere to keep metadata, so it is pulled out of JPEG/PNG sources before encoding,
+stored in a JSON sidecar next to the QOI file, and injected back into PNG/JPEG exports.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+use crate::qoi_errror::QoiError;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+
+const JPEG_APP0: u8 = 0xE0;
+const JPEG_APP1: u8 = 0xE1;
+const JPEG_APP2: u8 = 0xE2;
+const JPEG_SOS: u8 = 0xDA;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+// Largest payload of a JPEG segment, after its length field.
+const JPEG_SEGMENT_MAX: usize = 65533;
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+const SIDECAR_EXTENSION: &str = "meta.json";
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Metadata {
+    #[serde(with = "hex")]
+    pub icc_profile: Option<Vec<u8>>,
+    // Raw TIFF structure, without the JPEG "Exif" header.
+    #[serde(with = "hex")]
+    pub exif: Option<Vec<u8>>,
+    // EXIF orientation, 1 means upright.
+    pub orientation: Option<u16>,
+    // The orientation was applied to the pixels before encoding.
+    pub orientation_baked: bool,
+    // The source had a single color channel, exports restore it.
+    pub grayscale: bool,
+}
+
+impl Metadata {
+
+    /*
+    Extracts the metadata of the JPEG or PNG file at 'path', other formats have none.
+     */
+    pub fn read(path: &Path) -> Result<Self, QoiError> {
+        let bytes: Vec<u8> = fs::read(path)?;
+
+        let mut metadata: Metadata = if bytes.starts_with(&PNG_SIGNATURE) {
+            read_png(&bytes)
+        } else if bytes.starts_with(&JPEG_SOI) {
+            read_jpeg(&bytes)
+        } else {
+            Metadata::default()
+        };
+
+        if let Some(exif) = &metadata.exif {
+            metadata.orientation = orientation_offset(exif).map(|(offset, big_endian)| read_u16(&exif[offset..], big_endian));
+        }
+        Ok(metadata)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.icc_profile.is_none() && self.exif.is_none() && !self.grayscale
+    }
+
+    // Sidecar path of the QOI file at 'path'.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(SIDECAR_EXTENSION);
+        PathBuf::from(sidecar)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // Writes the sidecar of the QOI file at 'path', returns the sidecar's path.
+    pub fn save_sidecar(&self, path: &Path) -> Result<PathBuf, QoiError> {
+        let sidecar: PathBuf = Self::sidecar_path(path);
+        fs::write(&sidecar, self.to_json())?;
+        Ok(sidecar)
+    }
+
+    // Reads the sidecar of the QOI file at 'path', if there is one.
+    pub fn load_sidecar(path: &Path) -> Option<Self> {
+        let json: String = fs::read_to_string(Self::sidecar_path(path)).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    /*
+    Rotates/flips 'img' according to the EXIF orientation, marks it as baked.
+     */
+    pub fn bake_orientation(&mut self, img: DynamicImage) -> DynamicImage {
+        let baked: DynamicImage = match self.orientation {
+            Some(2) => img.fliph(),
+            Some(3) => img.rotate180(),
+            Some(4) => img.flipv(),
+            Some(5) => img.rotate90().fliph(),
+            Some(6) => img.rotate90(),
+            Some(7) => img.rotate270().fliph(),
+            Some(8) => img.rotate270(),
+            _ => return img,
+        };
+        self.orientation_baked = true;
+        baked
+    }
+
+    // EXIF to write back, the orientation is reset when it was baked into the pixels.
+    fn export_exif(&self) -> Option<Vec<u8>> {
+        let mut exif: Vec<u8> = self.exif.clone()?;
+        if self.orientation_baked {
+            if let Some((offset, big_endian)) = orientation_offset(&exif) {
+                let upright: [u8; 2] = if big_endian { 1u16.to_be_bytes() } else { 1u16.to_le_bytes() };
+                exif[offset..offset + 2].copy_from_slice(&upright);
+            }
+        }
+        Some(exif)
+    }
+
+    /*
+    Injects the metadata into the PNG or JPEG file at 'path', other formats are left as is.
+     */
+    pub fn apply(&self, path: &Path) -> Result<(), QoiError> {
+        let bytes: Vec<u8> = fs::read(path)?;
+
+        let injected: Vec<u8> = if bytes.starts_with(&PNG_SIGNATURE) {
+            self.inject_png(&bytes)
+        } else if bytes.starts_with(&JPEG_SOI) {
+            self.inject_jpeg(&bytes)
+        } else {
+            return Ok(());
+        };
+
+        fs::write(path, injected)?;
+        Ok(())
+    }
+
+    fn inject_png(&self, bytes: &[u8]) -> Vec<u8> {
+        // IHDR is always the first chunk, new chunks go right after it.
+        let ihdr_end: usize = PNG_SIGNATURE.len() + 8 + read_u32(&bytes[PNG_SIGNATURE.len()..]) as usize + 4;
+        let mut out: Vec<u8> = bytes[..ihdr_end].to_vec();
+
+        if let Some(icc) = &self.icc_profile {
+            let mut data: Vec<u8> = b"ICC Profile\0\0".to_vec();
+            data.extend(compress_to_vec_zlib(icc, 6));
+            write_png_chunk(&mut out, b"iCCP", &data);
+        }
+        if let Some(exif) = self.export_exif() {
+            write_png_chunk(&mut out, b"eXIf", &exif);
+        }
+
+        out.extend_from_slice(&bytes[ihdr_end..]);
+        out
+    }
+
+    fn inject_jpeg(&self, bytes: &[u8]) -> Vec<u8> {
+        // Keep SOI and a JFIF APP0 segment first.
+        let mut insert_at: usize = JPEG_SOI.len();
+        if bytes.get(insert_at + 1) == Some(&JPEG_APP0) {
+            insert_at += 2 + read_u16(&bytes[insert_at + 2..], true) as usize;
+        }
+        let mut out: Vec<u8> = bytes[..insert_at].to_vec();
+
+        if let Some(exif) = self.export_exif() {
+            let payload: Vec<u8> = [EXIF_HEADER, exif.as_slice()].concat();
+            if payload.len() <= JPEG_SEGMENT_MAX {
+                write_jpeg_segment(&mut out, JPEG_APP1, &payload);
+            }
+        }
+        if let Some(icc) = &self.icc_profile {
+            // Profiles are split over numbered APP2 segments.
+            let chunk_size: usize = JPEG_SEGMENT_MAX - ICC_HEADER.len() - 2;
+            let count: usize = icc.len().div_ceil(chunk_size);
+            if count <= u8::MAX as usize {
+                for (i, chunk) in icc.chunks(chunk_size).enumerate() {
+                    let payload: Vec<u8> = [ICC_HEADER, &[(i + 1) as u8, count as u8], chunk].concat();
+                    write_jpeg_segment(&mut out, JPEG_APP2, &payload);
+                }
+            }
+        }
+
+        out.extend_from_slice(&bytes[insert_at..]);
+        out
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    if big_endian {
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    } else {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+fn write_jpeg_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+fn read_png(bytes: &[u8]) -> Metadata {
+    let mut metadata: Metadata = Metadata::default();
+    let mut pos: usize = PNG_SIGNATURE.len();
+
+    while pos + 8 <= bytes.len() {
+        let length: usize = read_u32(&bytes[pos..]) as usize;
+        let kind: &[u8] = &bytes[pos + 4..pos + 8];
+        let Some(data) = bytes.get(pos + 8..pos + 8 + length) else { break };
+
+        match kind {
+            b"eXIf" => metadata.exif = Some(data.to_vec()),
+            b"iCCP" => {
+                // Profile name, null separator and compression method come first.
+                if let Some(name_end) = data.iter().position(|b| *b == 0) {
+                    metadata.icc_profile = data
+                        .get(name_end + 2..)
+                        .and_then(|profile| decompress_to_vec_zlib(profile).ok());
+                }
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => (),
+        }
+        pos += 8 + length + 4;
+    }
+    metadata
+}
+
+fn read_jpeg(bytes: &[u8]) -> Metadata {
+    let mut metadata: Metadata = Metadata::default();
+    let mut icc_chunks: Vec<(u8, &[u8])> = Vec::new();
+    let mut pos: usize = JPEG_SOI.len();
+
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker: u8 = bytes[pos + 1];
+        if marker == JPEG_SOS {
+            break;
+        }
+
+        let length: usize = read_u16(&bytes[pos + 2..], true) as usize;
+        let Some(payload) = bytes.get(pos + 4..pos + 2 + length) else { break };
+
+        if marker == JPEG_APP1 && payload.starts_with(EXIF_HEADER) {
+            metadata.exif = Some(payload[EXIF_HEADER.len()..].to_vec());
+        }
+        if marker == JPEG_APP2 && payload.starts_with(ICC_HEADER) && payload.len() > ICC_HEADER.len() + 2 {
+            icc_chunks.push((payload[ICC_HEADER.len()], &payload[ICC_HEADER.len() + 2..]));
+        }
+        pos += 2 + length;
+    }
+
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|(sequence, _)| *sequence);
+        metadata.icc_profile = Some(icc_chunks.iter().flat_map(|(_, chunk)| chunk.iter().copied()).collect());
+    }
+    metadata
+}
+
+/*
+Finds the orientation value inside the first IFD of a TIFF structure, returns its offset and byte order.
+ */
+fn orientation_offset(exif: &[u8]) -> Option<(usize, bool)> {
+    let big_endian: bool = match exif.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+
+    let ifd_bytes: [u8; 4] = exif.get(4..8)?.try_into().ok()?;
+    let ifd: usize = if big_endian { u32::from_be_bytes(ifd_bytes) } else { u32::from_le_bytes(ifd_bytes) } as usize;
+    let entries: usize = read_u16(exif.get(ifd..ifd + 2)?, big_endian) as usize;
+
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|entry| exif.get(*entry..*entry + 2).map(|tag| read_u16(tag, big_endian)) == Some(EXIF_ORIENTATION_TAG))
+        .map(|entry| entry + 8)
+        .filter(|value| *value + 2 <= exif.len())
+        .map(|value| (value, big_endian))
+}
+
+// Serializes optional byte buffers as hex strings, keeping sidecars readable.
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        let text: Option<String> = Option::deserialize(deserializer)?;
+        text.map(|text| {
+            (0..text.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(text.get(i..i + 2).unwrap_or("zz"), 16).map_err(serde::de::Error::custom))
+                .collect()
+        })
+        .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    // Big endian TIFF with a single IFD entry: orientation 6 (rotated 90 degrees).
+    const EXIF_ROTATED: &[u8] = b"MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qross_metadata_{}_{}", std::process::id(), name))
+    }
+
+    fn sample() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |x, y| image::Rgb([x as u8 * 5, y as u8 * 10, 0])))
+    }
+
+    #[test]
+    fn png_and_jpeg_keep_the_profile_and_exif() {
+        // Bigger than a JPEG segment, so the profile is split.
+        let metadata: Metadata = Metadata { icc_profile: Some(vec![7; 70000]), exif: Some(EXIF_ROTATED.to_vec()), ..Default::default() };
+
+        for name in ["source.png", "source.jpg"] {
+            let path: PathBuf = temp_file(name);
+            sample().save(&path).unwrap();
+            metadata.apply(&path).unwrap();
+
+            let read: Metadata = Metadata::read(&path).unwrap();
+            assert_eq!(read.icc_profile, metadata.icc_profile, "{}", name);
+            assert_eq!(read.exif, metadata.exif, "{}", name);
+            assert_eq!(read.orientation, Some(6), "{}", name);
+            assert_eq!(image::open(&path).unwrap().width(), 40, "{}", name);
+            fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn baked_orientation_is_reset_on_export() {
+        let mut metadata: Metadata = Metadata { exif: Some(EXIF_ROTATED.to_vec()), orientation: Some(6), ..Default::default() };
+        let baked: DynamicImage = metadata.bake_orientation(sample());
+        assert_eq!((baked.width(), baked.height()), (20, 40));
+        assert!(metadata.orientation_baked);
+
+        let path: PathBuf = temp_file("baked.png");
+        baked.save(&path).unwrap();
+        metadata.apply(&path).unwrap();
+        assert_eq!(Metadata::read(&path).unwrap().orientation, Some(1));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn sidecar_round_trip_and_bad_input() {
+        let qoi: PathBuf = temp_file("image.qoi");
+        let metadata: Metadata = Metadata { icc_profile: Some(vec![0, 1, 0xAB, 0xFF]), grayscale: true, ..Default::default() };
+        let sidecar: PathBuf = metadata.save_sidecar(&qoi).unwrap();
+
+        let loaded: Metadata = Metadata::load_sidecar(&qoi).unwrap();
+        assert_eq!(loaded.icc_profile, metadata.icc_profile);
+        assert!(loaded.grayscale && !loaded.is_empty());
+
+        // Odd or non-hex profiles make the sidecar unreadable rather than wrong.
+        fs::write(&sidecar, r#"{"icc_profile": "abc"}"#).unwrap();
+        assert!(Metadata::load_sidecar(&qoi).is_none());
+        fs::remove_file(sidecar).ok();
+
+        assert!(Metadata::load_sidecar(&qoi).is_none());
+        assert!(Metadata::read(&temp_file("missing.png")).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+/*
+Fidelity metrics between a source image and what Qross actually wrote.
+*/
+
+use std::borrow::Cow;
+use std::fmt;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::colorspace::{convert, ColorSpace};
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+
+const MAX_VALUE: f64 = 255.0;
+const SSIM_WINDOW: usize = 8;
+const SSIM_C1: f64 = (0.01 * MAX_VALUE) * (0.01 * MAX_VALUE);
+const SSIM_C2: f64 = (0.03 * MAX_VALUE) * (0.03 * MAX_VALUE);
+
+// Absolute error of a single channel.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ChannelError {
+    pub mean: f64,
+    pub max: u8,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct QualityReport {
+    // Infinite when both buffers are identical.
+    pub psnr: f64,
+    pub ssim: f64,
+    pub channels: Vec<ChannelError>,
+}
+
+impl QualityReport {
+    pub fn between(source: &[u8], output: &[u8], width: u32, channels: usize) -> Self {
+        Self {
+            psnr: psnr(source, output),
+            ssim: ssim(source, output, width as usize, channels),
+            channels: channel_errors(source, output, channels),
+        }
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.channels.iter().all(|c| c.max == 0)
+    }
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PSNR {:.2} dB, SSIM {:.4}", self.psnr, self.ssim)?;
+        for (c, error) in self.channels.iter().enumerate() {
+            write!(f, ", channel {} mean {:.3} max {}", c, error.mean, error.max)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+Compares 'source' against a decoded QOI file, using the channels of the QOI file.
+Output stored in another colorspace is converted back to the source's first, so the conversion's loss shows up.
+ */
+pub fn compare(source: &DynamicImage, output: &QoiFile) -> Result<QualityReport, QoiError> {
+    if source.width() != output.width || source.height() != output.height {
+        return Err(QoiError::DimensionMismatch(format!(
+            "source is {}x{}, output is {}x{}",
+            source.width(), source.height(), output.width, output.height
+        )));
+    }
+
+    let source_pixels: Vec<u8> = if output.channels == 4 {
+        source.to_rgba8().into_raw()
+    } else {
+        source.to_rgb8().into_raw()
+    };
+
+    if source_pixels.len() != output.pixels.len() {
+        return Err(QoiError::DimensionMismatch(format!(
+            "source holds {} bytes, output holds {} bytes",
+            source_pixels.len(), output.pixels.len()
+        )));
+    }
+
+    let mut output_pixels: Cow<[u8]> = Cow::Borrowed(&output.pixels);
+    let source_space: ColorSpace = ColorSpace::of_image(source);
+    if output.colorspace() != source_space {
+        convert(output_pixels.to_mut(), output.channels as usize, output.colorspace(), source_space);
+    }
+
+    Ok(QualityReport::between(&source_pixels, &output_pixels, source.width(), output.channels as usize))
+}
+
+pub fn channel_errors(source: &[u8], output: &[u8], channels: usize) -> Vec<ChannelError> {
+    assert_eq!(source.len(), output.len());
+
+    let pixel_count: f64 = (source.len() / channels).max(1) as f64;
+    (0..channels)
+        .map(|c| {
+            let diffs = source
+                .iter()
+                .skip(c)
+                .step_by(channels)
+                .zip(output.iter().skip(c).step_by(channels))
+                .map(|(s, o)| s.abs_diff(*o));
+
+            let (sum, max) = diffs.fold((0u64, 0u8), |(sum, max), d| (sum + d as u64, max.max(d)));
+            ChannelError { mean: sum as f64 / pixel_count, max }
+        })
+        .collect()
+}
+
+pub fn mse(source: &[u8], output: &[u8]) -> f64 {
+    assert_eq!(source.len(), output.len());
+
+    let sum: f64 = source
+        .iter()
+        .zip(output)
+        .map(|(s, o)| {
+            let diff: f64 = *s as f64 - *o as f64;
+            diff * diff
+        })
+        .sum();
+
+    sum / source.len().max(1) as f64
+}
+
+pub fn psnr(source: &[u8], output: &[u8]) -> f64 {
+    let mse: f64 = mse(source, output);
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (MAX_VALUE * MAX_VALUE / mse).log10()
+}
+
+/*
+Mean SSIM over non-overlapping 8x8 windows of every channel.
+ */
+pub fn ssim(source: &[u8], output: &[u8], width: usize, channels: usize) -> f64 {
+    assert_eq!(source.len(), output.len());
+
+    let height: usize = source.len() / (width * channels).max(1);
+    let mut total: f64 = 0.0;
+    let mut windows: usize = 0;
+
+    for c in 0..channels {
+        for wy in (0..height).step_by(SSIM_WINDOW) {
+            for wx in (0..width).step_by(SSIM_WINDOW) {
+                let offsets = (wy..(wy + SSIM_WINDOW).min(height))
+                    .flat_map(|y| (wx..(wx + SSIM_WINDOW).min(width)).map(move |x| (y * width + x) * channels + c));
+
+                let (mut sum_s, mut sum_o, mut sum_ss, mut sum_oo, mut sum_so) = (0.0, 0.0, 0.0, 0.0, 0.0);
+                let mut n: f64 = 0.0;
+                for offset in offsets {
+                    let s: f64 = source[offset] as f64;
+                    let o: f64 = output[offset] as f64;
+                    sum_s += s;
+                    sum_o += o;
+                    sum_ss += s * s;
+                    sum_oo += o * o;
+                    sum_so += s * o;
+                    n += 1.0;
+                }
+
+                let (mean_s, mean_o) = (sum_s / n, sum_o / n);
+                let var_s: f64 = sum_ss / n - mean_s * mean_s;
+                let var_o: f64 = sum_oo / n - mean_o * mean_o;
+                let covar: f64 = sum_so / n - mean_s * mean_o;
+
+                total += ((2.0 * mean_s * mean_o + SSIM_C1) * (2.0 * covar + SSIM_C2))
+                    / ((mean_s * mean_s + mean_o * mean_o + SSIM_C1) * (var_s + var_o + SSIM_C2));
+                windows += 1;
+            }
+        }
+    }
+
+    if windows == 0 {
+        return 1.0;
+    }
+    total / windows as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use image::RgbImage;
+    use crate::comp::fixtures::sample;
+
+    fn source() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_raw(20, 12, sample(20, 12, 3)).unwrap())
+    }
+
+    fn output(pixels: Vec<u8>, color_space: ColorSpace) -> QoiFile {
+        QoiFile {
+            path: PathBuf::new(),
+            size: 0,
+            width: 20,
+            height: 12,
+            channels: 3,
+            color_space: color_space.header_byte(),
+            pixels,
+            trailer: None,
+        }
+    }
+
+    #[test]
+    fn identical_images_are_lossless() {
+        let report: QualityReport = compare(&source(), &output(sample(20, 12, 3), ColorSpace::Srgb)).unwrap();
+        assert_eq!(report.psnr, f64::INFINITY);
+        assert_eq!(report.ssim, 1.0);
+        assert!(report.is_lossless());
+    }
+
+    #[test]
+    fn one_level_offset() {
+        let shifted: Vec<u8> = sample(20, 12, 3).iter().map(|v| v + 1).collect();
+        let report: QualityReport = compare(&source(), &output(shifted, ColorSpace::Srgb)).unwrap();
+
+        assert!(report.channels.iter().all(|c| c.mean == 1.0 && c.max == 1));
+        assert!((report.psnr - 20.0 * MAX_VALUE.log10()).abs() < 1e-9);
+        assert!(report.ssim < 1.0 && report.ssim > 0.99);
+        assert!(!report.is_lossless());
+    }
+
+    #[test]
+    fn linear_output_is_compared_in_the_source_colorspace() {
+        let mut pixels: Vec<u8> = sample(20, 12, 3);
+        convert(&mut pixels, 3, ColorSpace::Srgb, ColorSpace::Linear);
+        let report: QualityReport = compare(&source(), &output(pixels.clone(), ColorSpace::Linear)).unwrap();
+        let raw: f64 = psnr(&sample(20, 12, 3), &pixels);
+
+        assert!(report.psnr > raw + 10.0, "{} against {}", report.psnr, raw);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_rejected() {
+        let mut wrong_size: QoiFile = output(sample(20, 12, 3), ColorSpace::Srgb);
+        wrong_size.height = 11;
+        assert!(matches!(compare(&source(), &wrong_size), Err(QoiError::DimensionMismatch(_))));
+
+        let wrong_channels: QoiFile = QoiFile { channels: 4, ..output(sample(20, 12, 3), ColorSpace::Srgb) };
+        assert!(matches!(compare(&source(), &wrong_channels), Err(QoiError::DimensionMismatch(_))));
+    }
+}
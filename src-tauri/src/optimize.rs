@@ -0,0 +1,158 @@
+/*
+Optimize mode: tries reversible color transforms before encoding and keeps the one giving the smallest stream.
+QOI's LUMA chunk is built around green, so which channel plays green, and whether the others
+are stored relative to it, changes how many pixels fit the 1 and 2 byte chunks.
+The chosen transform is recorded in the container trailer and undone when the file is opened,
+so optimize only runs on files written with a trailer.
+
+Op choices are not searched: the decoder's state (previous pixel and index) only depends on the pixels,
+never on the chunk used for them, so the encoder's shortest legal chunk per pixel is already the smallest stream.
+*/
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::comp::encode_image;
+use crate::colorspace::ColorSpace;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ColorTransform {
+    // Source channel stored in each of the R, G and B slots.
+    pub order: [u8; 3],
+    // R and B are stored minus G (wrapping).
+    pub subtract_green: bool,
+}
+
+impl Default for ColorTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+// Result of the search.
+#[derive(Clone, Copy, Debug)]
+pub struct Optimized {
+    pub transform: ColorTransform,
+    // Bytes saved against the plain encoding.
+    pub saved: usize,
+}
+
+impl ColorTransform {
+
+    pub const IDENTITY: Self = Self { order: [0, 1, 2], subtract_green: false };
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    // Trailer form: the three slots' source channels, then 1 when green is subtracted.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        [self.order[0], self.order[1], self.order[2], self.subtract_green as u8]
+    }
+
+    // Reads 'to_bytes', None unless the order is a permutation of the RGB channels.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let [o0, o1, o2, subtract_green] = bytes else {
+            return None;
+        };
+        let order: [u8; 3] = [*o0, *o1, *o2];
+        let mut sorted: [u8; 3] = order;
+        sorted.sort_unstable();
+        if sorted != [0, 1, 2] || *subtract_green > 1 {
+            return None;
+        }
+        Some(Self { order, subtract_green: *subtract_green == 1 })
+    }
+
+    // Every channel order, with and without subtracting green.
+    pub fn candidates() -> Vec<Self> {
+        const ORDERS: [[u8; 3]; 6] = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        ORDERS
+            .iter()
+            .flat_map(|order| [false, true].map(|subtract_green| Self { order: *order, subtract_green }))
+            .collect()
+    }
+
+    /*
+    Applies the transform to the RGB part of 'pixels' ('channels' bytes per pixel, at least 3).
+     */
+    pub fn forward(&self, pixels: &mut [u8], channels: usize) {
+        let [o0, o1, o2] = self.order.map(|o| o as usize);
+        for px in pixels.chunks_exact_mut(channels) {
+            let mut p: [u8; 3] = [px[o0], px[o1], px[o2]];
+            if self.subtract_green {
+                p[0] = p[0].wrapping_sub(p[1]);
+                p[2] = p[2].wrapping_sub(p[1]);
+            }
+            px[..3].copy_from_slice(&p);
+        }
+    }
+
+    // Undoes 'forward'.
+    pub fn inverse(&self, pixels: &mut [u8], channels: usize) {
+        for px in pixels.chunks_exact_mut(channels) {
+            let mut p: [u8; 3] = [px[0], px[1], px[2]];
+            if self.subtract_green {
+                p[0] = p[0].wrapping_add(p[1]);
+                p[2] = p[2].wrapping_add(p[1]);
+            }
+            for (slot, source) in self.order.iter().enumerate() {
+                px[*source as usize] = p[slot];
+            }
+        }
+    }
+}
+
+/*
+Encodes 'pixels' with every candidate transform in parallel and returns the smallest one.
+Gray layouts have nothing to reorder and keep the identity.
+ */
+pub fn optimize(pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace) -> Optimized {
+    if channels < 3 {
+        return Optimized { transform: ColorTransform::IDENTITY, saved: 0 };
+    }
+
+    let sizes: Vec<(ColorTransform, usize)> = ColorTransform::candidates()
+        .into_par_iter()
+        .map(|transform| {
+            let mut transformed: Vec<u8> = pixels.to_vec();
+            transform.forward(&mut transformed, channels);
+            (transform, encode_image(&transformed, channels, width, height, color_space).len())
+        })
+        .collect();
+
+    // The identity comes first, so ties keep the plain encoding.
+    let plain: usize = sizes[0].1;
+    let (transform, best) = sizes.into_iter().fold((ColorTransform::IDENTITY, plain), |best, candidate| {
+        if candidate.1 < best.1 { candidate } else { best }
+    });
+
+    Optimized { transform, saved: plain - best }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_candidate_is_undone_by_its_inverse() {
+        let source: Vec<u8> = (0..4 * 50).map(|i| (i * 37 + 11) as u8).collect();
+        for channels in 3..=4 {
+            for transform in ColorTransform::candidates() {
+                let mut pixels: Vec<u8> = source.clone();
+                transform.forward(&mut pixels, channels);
+                transform.inverse(&mut pixels, channels);
+                assert_eq!(pixels, source, "{:?}", transform);
+                assert_eq!(ColorTransform::from_bytes(&transform.to_bytes()), Some(transform));
+            }
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_what_no_candidate_writes() {
+        assert_eq!(ColorTransform::from_bytes(&[0, 0, 2, 0]), None);
+        assert_eq!(ColorTransform::from_bytes(&[0, 1, 3, 0]), None);
+        assert_eq!(ColorTransform::from_bytes(&[0, 1, 2, 2]), None);
+        assert_eq!(ColorTransform::from_bytes(&[0, 1, 2]), None);
+    }
+}
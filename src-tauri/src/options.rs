@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::quantize::LossyOptions;
+use crate::colorspace::ColorSpace;
+use crate::entropy::Entropy;
+use crate::depth::DepthStrategy;
+use crate::format::FormatPolicy;
+use crate::transform::{ChannelMode, Transform};
+use crate::qoi_errror::QoiError;
+
+// Settings applied to every Data inside a Package when compressing.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct CompressOptions {
+    // Lossy pre-quantization of the pixel buffer, None keeps the encoder lossless.
+    pub lossy: Option<LossyOptions>,
+    // Encode horizontal stripes of a single image on all cores.
+    pub parallel: bool,
+    // Colorspace written into the header, None keeps the source's.
+    pub colorspace: Option<ColorSpace>,
+    // Apply the EXIF orientation to the pixels before encoding.
+    pub bake_orientation: bool,
+    // Append a Qross trailer with checksums and metadata after the end mark.
+    pub container: bool,
+    // Second compression stage around the QOI stream, None writes plain QOI.
+    pub entropy: Option<Entropy>,
+    // Write a tiled container with tiles of this size instead of a single stream.
+    // Tiled files are read by seeking, so they get no trailer or second stage.
+    pub tile_size: Option<u32>,
+    // Store animation frames as differences to the previous frame when that is smaller.
+    pub frame_diff: bool,
+    // How 16 bit and float sources are brought down to 8 bits.
+    pub depth: DepthStrategy,
+    // Keep 16 bit sources lossless by also encoding their low bytes as a second image.
+    pub split_planes: bool,
+    // Applied in order to every image right after it is opened.
+    pub transforms: Vec<Transform>,
+    // Layout the images are brought to after the transforms.
+    pub channel_mode: ChannelMode,
+    // Folder of the encoded files, None uses IMG_FOLDER_PATH.
+    pub output_dir: Option<String>,
+    // Name of the encoded files without extension, "{name}" is the source's name. None uses "{name}_encoded".
+    pub naming: Option<String>,
+    // Don't decode the written file to compare it against the source.
+    pub skip_verify: bool,
+    // Search reversible color transforms for the smallest output, needs 'container' to record the one picked.
+    pub optimize: bool,
+    // Count what every op of the written stream costs, single stream files only.
+    pub stats: bool,
+    // Write QOI, QOI + zstd or PNG, whichever the policy picks for every image. Single stream files only.
+    pub auto_format: Option<FormatPolicy>,
+}
+
+const DEFAULT_NAMING: &str = "{name}_encoded";
+
+impl CompressOptions {
+
+    // Encoded file name of the source called 'name', without extension.
+    pub fn file_stem(&self, name: &str) -> String {
+        self.naming.as_deref().unwrap_or(DEFAULT_NAMING).replace("{name}", name)
+    }
+
+    // Reads a preset file, the JSON form of CompressOptions. Missing fields keep their defaults.
+    pub fn load(path: &Path) -> Result<Self, QoiError> {
+        let json: String = fs::read_to_string(path)?;
+        serde_json::from_str(&json)
+            .map_err(|e| QoiError::InvalidArgument(format!("invalid preset '{}': {}", path.display(), e)))
+    }
+}
@@ -9,11 +9,15 @@ pub struct Pixel {
 }
 
 impl Pixel {
+
+    // Index slots before the stream writes them, all zeros as the spec says, transparent unlike 'zero'.
+    pub const INDEX_START: Pixel = Pixel { r: 0, g: 0, b: 0, a: 0 };
+
     pub fn to_bytes(&self) -> [u8; CHANNELS as usize] { 
         return [self.r, self.g, self.b];
     }
     pub fn hash(&self) -> usize {
-        self.r as usize * 3 + self.g as usize * 5 + self.b as usize * 7
+        self.r as usize * 3 + self.g as usize * 5 + self.b as usize * 7 + self.a as usize * 11
     }
 }
 
@@ -0,0 +1,99 @@
+/*
+Named compression presets, stored in the database and shared as JSON files.
+*/
+
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::options::CompressOptions;
+use crate::qoi_errror::QoiError;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    pub options: CompressOptions,
+}
+
+impl Preset {
+
+    pub fn options_json(&self) -> String {
+        serde_json::to_string(&self.options).unwrap()
+    }
+
+    // Builds a preset from a database row.
+    pub fn from_row(name: String, options: &str) -> Result<Self, QoiError> {
+        let options: CompressOptions = serde_json::from_str(options)
+            .map_err(|e| QoiError::InvalidArgument(format!("invalid preset '{}': {}", name, e)))?;
+        Ok(Self { name, options })
+    }
+}
+
+/*
+Writes 'presets' to 'path' as a JSON array.
+ */
+pub fn export(presets: &[Preset], path: &Path) -> Result<(), QoiError> {
+    fs::write(path, serde_json::to_string_pretty(presets).unwrap())?;
+    Ok(())
+}
+
+/*
+Reads the JSON array of presets at 'path'.
+ */
+pub fn import(path: &Path) -> Result<Vec<Preset>, QoiError> {
+    let json: String = fs::read_to_string(path)?;
+    serde_json::from_str(&json)
+        .map_err(|e| QoiError::InvalidArgument(format!("invalid preset file '{}': {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::entropy::Entropy;
+    use crate::transform::Transform;
+
+    fn preset(name: &str) -> Preset {
+        let options: CompressOptions = CompressOptions {
+            container: true,
+            entropy: Some(Entropy::Lz4),
+            transforms: vec![Transform::Rotate90, "fit=64x64".parse().unwrap()],
+            naming: Some("{name}_small".to_string()),
+            ..Default::default()
+        };
+        Preset { name: name.to_string(), options }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qross_preset_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn database_rows_round_trip() {
+        let original: Preset = preset("thumbnails");
+        let read: Preset = Preset::from_row(original.name.clone(), &original.options_json()).unwrap();
+        assert_eq!(read.name, "thumbnails");
+        assert_eq!(read.options_json(), original.options_json());
+
+        // Fields left out of a stored preset take their defaults.
+        assert!(Preset::from_row("empty".to_string(), "{}").unwrap().options.entropy.is_none());
+        assert!(matches!(Preset::from_row("broken".to_string(), "{\"container\": 1}"), Err(QoiError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let path: PathBuf = temp_file("presets.json");
+        let presets: Vec<Preset> = vec![preset("a"), preset("b")];
+        export(&presets, &path).unwrap();
+
+        let imported: Vec<Preset> = import(&path).unwrap();
+        assert_eq!(imported.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>(), ["a", "b"]);
+        assert_eq!(imported[1].options_json(), presets[1].options_json());
+
+        fs::write(&path, "[{\"name\": \"no options\"}]").unwrap();
+        assert!(matches!(import(&path), Err(QoiError::InvalidArgument(_))));
+        fs::remove_file(&path).ok();
+
+        assert!(matches!(import(&path), Err(QoiError::GeneralIOError(_))));
+    }
+}
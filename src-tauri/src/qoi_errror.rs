@@ -1,11 +1,18 @@
 use std::io;
 use std::fmt;
+use image::ImageFormat;
+use image::error::{DecodingError, EncodingError, ImageFormatHint, ParameterError, ParameterErrorKind};
 
 pub enum QoiError {
     InvalidHeader(String),
     InvalidEndMark(String),
     SavingError(String),
+    DimensionMismatch(String),
+    InvalidArgument(String),
+    InvalidTrailer(String),
+    ChecksumMismatch(String),
     GeneralIOError(std::io::Error),
+    ImageError(image::ImageError),
 }
 
 impl From<io::Error> for QoiError {
@@ -14,13 +21,41 @@ impl From<io::Error> for QoiError {
     }
 }
 
+impl From<image::ImageError> for QoiError {
+    fn from(error: image::ImageError) -> Self {
+        QoiError::ImageError(error)
+    }
+}
+
+impl std::error::Error for QoiError {}
+
+// Our errors in the `image` crate's terms, for the ImageEncoder and ImageDecoder impls.
+impl From<QoiError> for image::ImageError {
+    fn from(error: QoiError) -> Self {
+        let format = || ImageFormatHint::Exact(ImageFormat::Qoi);
+        match error {
+            QoiError::GeneralIOError(err) => image::ImageError::IoError(err),
+            QoiError::ImageError(err) => err,
+            QoiError::DimensionMismatch(_) => image::ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)),
+            QoiError::InvalidArgument(err) => image::ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(err))),
+            QoiError::SavingError(_) => image::ImageError::Encoding(EncodingError::new(format(), error)),
+            _ => image::ImageError::Decoding(DecodingError::new(format(), error)),
+        }
+    }
+}
+
 impl fmt::Display for QoiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             QoiError::InvalidHeader(err) => write!(f, "Invalid MAGICheader error: {}", err),
             QoiError::InvalidEndMark(err) => write!(f, "Invalid end mark error: {}", err),
             QoiError::SavingError(err) => write!(f, "Saving buffer into QOI file resulted an error: {}", err),
+            QoiError::DimensionMismatch(err) => write!(f, "Dimension mismatch error: {}", err),
+            QoiError::InvalidArgument(err) => write!(f, "Invalid argument error: {}", err),
+            QoiError::InvalidTrailer(err) => write!(f, "Invalid trailer error: {}", err),
+            QoiError::ChecksumMismatch(err) => write!(f, "Checksum mismatch error: {}", err),
             QoiError::GeneralIOError(err) => write!(f, "General io error: {}", err),
+            QoiError::ImageError(err) => write!(f, "Image error: {}", err),
         }
     }
 }
@@ -31,7 +66,12 @@ impl fmt::Debug for QoiError {
             QoiError::InvalidHeader(err) => write!(f, "Invalid header error: {}", err),
             QoiError::InvalidEndMark(err) => write!(f, "Invalid end mark error: {}", err),
             QoiError::SavingError(err) => write!(f, "Saving buffer into QOI file resulted an error: {}", err),
+            QoiError::DimensionMismatch(err) => write!(f, "Dimension mismatch error: {}", err),
+            QoiError::InvalidArgument(err) => write!(f, "Invalid argument error: {}", err),
+            QoiError::InvalidTrailer(err) => write!(f, "Invalid trailer error: {}", err),
+            QoiError::ChecksumMismatch(err) => write!(f, "Checksum mismatch error: {}", err),
             QoiError::GeneralIOError(err) => write!(f, "General io error: {}", err),
+            QoiError::ImageError(err) => write!(f, "Image error: {}", err),
         }
     }
 }
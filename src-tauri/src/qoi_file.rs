@@ -1,7 +1,17 @@
-use std::path::PathBuf;
-use image::{ImageBuffer, ImageFormat, Rgb};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::BufWriter;
+use image::{ColorType, DynamicImage, ImageEncoder, ImageError, ImageResult, RgbImage, RgbaImage};
 
-use crate::pixel::Pixel;
+use crate::comp::decode_bytes;
+use crate::codec::QrossEncoder;
+use crate::options::CompressOptions;
+use crate::colorspace::{convert, ColorSpace};
+use crate::metadata::Metadata;
+use crate::container::Trailer;
+use crate::consts::QOI_TILED_MAGIC;
+use crate::tiles::TiledFile;
 use crate::qoi_errror::QoiError;
 
 #[derive(Clone)]
@@ -12,28 +22,89 @@ pub struct QoiFile {
     pub height: u32,
     pub channels: u8,
     pub color_space: u8,
-    pub pixels: Vec<Pixel>,
+    // Flat buffer, 'channels' bytes per pixel.
+    pub pixels: Vec<u8>,
+    // Qross container trailer, already verified against the pixels.
+    pub trailer: Option<Trailer>,
 }
 
 impl QoiFile {
-    pub fn set_size(&mut self) { 
-        self.size = (self.width * self.height * self.channels as u32) as usize;
+
+    // Decodes the QOI file at 'path', tiled containers are decoded whole.
+    pub fn open(path: &Path) -> Result<QoiFile, QoiError> {
+        let bytes: Vec<u8> = fs::read(path)?;
+        let mut qoi_file: QoiFile = if bytes.starts_with(&QOI_TILED_MAGIC) {
+            TiledFile::open(path)?.read_all()?
+        } else {
+            decode_bytes(&bytes, path.to_path_buf())?
+        };
+        qoi_file.set_size();
+        qoi_file.restore_colors();
+        Ok(qoi_file)
     }
-    
-    pub fn parse_pixels_to_vec(&self, px_buffer: &mut Vec<u8>) {
-        self.pixels.iter().for_each(
-            |px| px_buffer.extend(px.to_bytes())
-        )
+
+    // Undoes the color transform the optimize mode recorded in the trailer.
+    pub fn restore_colors(&mut self) {
+        if let Some(transform) = self.trailer.as_ref().and_then(|t| t.color_transform) {
+            transform.inverse(&mut self.pixels, self.channels as usize);
+        }
+    }
+
+    // Colorspace found in the header.
+    pub fn colorspace(&self) -> ColorSpace {
+        ColorSpace::from_header(self.color_space)
+    }
+
+    pub fn to_image(&self) -> DynamicImage {
+        if self.channels == 4 {
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(self.width, self.height, self.pixels.clone()).unwrap())
+        } else {
+            DynamicImage::ImageRgb8(RgbImage::from_raw(self.width, self.height, self.pixels.clone()).unwrap())
+        }
     }
-    
-    pub fn create(&mut self, path: PathBuf) {
-        let mut px_buffer: Vec<u8> = Vec::with_capacity(self.size);
-        self.parse_pixels_to_vec(&mut px_buffer);
 
-        let converted: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_raw(self.width, self.height, px_buffer).unwrap();
+    /*
+    Saves the pixels to 'path' in the format given by its extension, converted into 'color_space'.
+    PNG and JPEG viewers expect sRGB. ICC profile, EXIF and a grayscale source from the sidecar are restored.
+     */
+    pub fn export(&self, path: &Path, color_space: ColorSpace) -> Result<(), QoiError> {
+        let mut img: DynamicImage = self.to_image();
+        let channels: usize = self.channels as usize;
+
+        match &mut img {
+            DynamicImage::ImageRgba8(buffer) => convert(buffer, channels, self.colorspace(), color_space),
+            DynamicImage::ImageRgb8(buffer) => convert(buffer, channels, self.colorspace(), color_space),
+            _ => (),
+        }
+
+        // Put back what the source had, if a sidecar was written for this file.
+        let metadata: Option<Metadata> = Metadata::load_sidecar(&self.path);
+        if metadata.as_ref().is_some_and(|m| m.grayscale) {
+            img = DynamicImage::ImageLuma8(img.to_luma8());
+        }
+
+        img.save(path)?;
+        if let Some(metadata) = metadata {
+            metadata.apply(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_size(&mut self) { 
+        self.size = self.width as usize * self.height as usize * self.channels as usize;
+    }
+    
+    // Writes the pixels to 'path' through our encoder, keeping the header colorspace.
+    pub fn create(&self, path: &Path) -> Result<(), QoiError> {
+        let color_type: ColorType = if self.channels == 4 { ColorType::Rgba8 } else { ColorType::Rgb8 };
+        let options: CompressOptions = CompressOptions { colorspace: Some(self.colorspace()), ..Default::default() };
+        let saved: ImageResult<()> = File::create(path)
+            .map_err(ImageError::IoError)
+            .and_then(|file| {
+                QrossEncoder::with_options(BufWriter::new(file), &options)
+                    .write_image(&self.pixels, self.width, self.height, color_type)
+            });
 
-        converted.save_with_format(path, ImageFormat::Qoi).expect(&format!("{}", 
-            QoiError::SavingError(format!("Failed at saving file"))
-        ));
+        saved.map_err(|e| QoiError::SavingError(format!("{}: {}", path.display(), e)))
     }
 }
@@ -0,0 +1,305 @@
+/*
+Lossy pre-quantization of a pixel buffer, applied right before the QOI encoder.
+Fewer distinct values means more runs, index hits and small diffs, at the cost of fidelity.
+*/
+
+use std::str::FromStr;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::qoi_errror::QoiError;
+
+pub const MAX_QUALITY: u8 = 100;
+
+// Thresholds used by ordered dithering.
+pub(crate) const BAYER_4X4: [[u8; 4]; 4] = [
+    [ 0,  8,  2, 10],
+    [12,  4, 14,  6],
+    [ 3, 11,  1,  9],
+    [15,  7, 13,  5],
+];
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum QuantizeMethod {
+    BitDepth,
+    Ordered,
+    FloydSteinberg,
+    Palette,
+}
+
+impl FromStr for QuantizeMethod {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitdepth" => Ok(QuantizeMethod::BitDepth),
+            "ordered" => Ok(QuantizeMethod::Ordered),
+            "floyd-steinberg" => Ok(QuantizeMethod::FloydSteinberg),
+            "palette" => Ok(QuantizeMethod::Palette),
+            _ => Err(QoiError::InvalidArgument(format!("unknown quantize method '{}'", s))),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct LossyOptions {
+    pub method: QuantizeMethod,
+    // 0 is the smallest output, 100 keeps every bit.
+    pub quality: u8,
+}
+
+impl LossyOptions {
+
+    // Bits kept per channel, 1 at quality 0 up to 8 at quality 100.
+    pub fn bits(&self) -> u8 {
+        1 + (self.quality.min(MAX_QUALITY) as u16 * 7 / MAX_QUALITY as u16) as u8
+    }
+
+    // Palette entries, 2 at quality 0 up to 256 at quality 100.
+    pub fn palette_size(&self) -> usize {
+        2 + self.quality.min(MAX_QUALITY) as usize * 254 / MAX_QUALITY as usize
+    }
+}
+
+/*
+Quantizes 'pixels' in place, 'channels' is the amount of bytes per pixel.
+Only the color channels change, gray layouts have one and alpha is left untouched.
+ */
+pub fn quantize(pixels: &mut [u8], width: u32, channels: usize, options: &LossyOptions) {
+    let width: u32 = width.max(1);
+    match options.method {
+        QuantizeMethod::BitDepth => bit_depth(pixels, channels, options.bits()),
+        QuantizeMethod::Ordered => ordered(pixels, width as usize, channels, options.bits()),
+        QuantizeMethod::FloydSteinberg => floyd_steinberg(pixels, width as usize, channels, options.bits()),
+        QuantizeMethod::Palette => palette(pixels, channels, options.palette_size()),
+    }
+}
+
+// Snaps 'value' to the closest of 2^bits levels spread evenly between 0 and 255.
+fn snap(value: f32, bits: u8) -> u8 {
+    let steps: f32 = ((1u16 << bits) - 1) as f32;
+    let level: f32 = (value.clamp(0.0, 255.0) * steps / 255.0).round();
+    (level * 255.0 / steps).round() as u8
+}
+
+// Gray layouts hold a single color channel.
+fn color_channels(channels: usize) -> usize {
+    if channels < 3 { 1 } else { 3 }
+}
+
+fn step(bits: u8) -> f32 {
+    255.0 / ((1u16 << bits) - 1) as f32
+}
+
+fn bit_depth(pixels: &mut [u8], channels: usize, bits: u8) {
+    for px in pixels.chunks_exact_mut(channels) {
+        px[..color_channels(channels)].iter_mut().for_each(|v| *v = snap(*v as f32, bits));
+    }
+}
+
+fn ordered(pixels: &mut [u8], width: usize, channels: usize, bits: u8) {
+    let step: f32 = step(bits);
+
+    for (i, px) in pixels.chunks_exact_mut(channels).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let threshold: f32 = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+
+        px[..color_channels(channels)].iter_mut().for_each(|v| *v = snap(*v as f32 + threshold * step, bits));
+    }
+}
+
+fn floyd_steinberg(pixels: &mut [u8], width: usize, channels: usize, bits: u8) {
+    // A last row shorter than 'width' is quantized too.
+    let pixel_count: usize = pixels.len() / channels;
+    let height: usize = pixel_count.div_ceil(width);
+    let mut work: Vec<f32> = pixels.iter().map(|v| *v as f32).collect();
+
+    // Spread 'error' into the pixel at (x, y) if it exists.
+    let spread = |work: &mut Vec<f32>, x: isize, y: usize, c: usize, error: f32| {
+        if x >= 0 && (x as usize) < width && y * width + (x as usize) < pixel_count {
+            work[(y * width + x as usize) * channels + c] += error;
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width.min(pixel_count - y * width) {
+            for c in 0..color_channels(channels) {
+                let offset: usize = (y * width + x) * channels + c;
+                let quantized: u8 = snap(work[offset], bits);
+                let error: f32 = work[offset] - quantized as f32;
+                pixels[offset] = quantized;
+
+                let x: isize = x as isize;
+                spread(&mut work, x + 1, y, c, error * 7.0 / 16.0);
+                spread(&mut work, x - 1, y + 1, c, error * 3.0 / 16.0);
+                spread(&mut work, x, y + 1, c, error * 5.0 / 16.0);
+                spread(&mut work, x + 1, y + 1, c, error * 1.0 / 16.0);
+            }
+        }
+    }
+}
+
+// Returns the channel with the widest value range inside 'colors' and that range.
+fn widest_channel(colors: &[([u8; 3], u32)]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min: u8 = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+            let max: u8 = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/*
+Median cut palette quantization of the color channels, alpha (if any) is left untouched.
+A gray value is handled as the color with r, g and b equal to it.
+ */
+fn palette(pixels: &mut [u8], channels: usize, size: usize) {
+    let color_channels: usize = color_channels(channels);
+    let color = |px: &[u8]| -> [u8; 3] {
+        if color_channels == 1 { [px[0]; 3] } else { [px[0], px[1], px[2]] }
+    };
+
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    for px in pixels.chunks_exact(channels) {
+        *histogram.entry(color(px)).or_insert(0) += 1;
+    }
+
+    // The image already fits inside the palette.
+    if histogram.len() <= size {
+        return;
+    }
+
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![histogram.into_iter().collect()];
+    while boxes.len() < size {
+        let (index, channel, range) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+            .unwrap();
+
+        // Every box holds a single color.
+        if range == 0 {
+            break;
+        }
+
+        let mut lower: Vec<([u8; 3], u32)> = boxes.swap_remove(index);
+        lower.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        // Split at the weighted median.
+        let total: u64 = lower.iter().map(|(_, count)| *count as u64).sum();
+        let mut seen: u64 = 0;
+        let split: usize = lower
+            .iter()
+            .position(|(_, count)| {
+                seen += *count as u64;
+                seen * 2 >= total
+            })
+            .map_or(1, |i| i + 1)
+            .clamp(1, lower.len() - 1);
+
+        let upper: Vec<([u8; 3], u32)> = lower.split_off(split);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    // Every color is replaced by the weighted average of its box.
+    let mut mapping: HashMap<[u8; 3], [u8; 3]> = HashMap::new();
+    for b in &boxes {
+        let total: u64 = b.iter().map(|(_, count)| *count as u64).sum();
+        let mut average: [u8; 3] = [0; 3];
+        for (c, value) in average.iter_mut().enumerate() {
+            let sum: u64 = b.iter().map(|(color, count)| color[c] as u64 * *count as u64).sum();
+            *value = ((sum + total / 2) / total) as u8;
+        }
+        b.iter().for_each(|(color, _)| { mapping.insert(*color, average); });
+    }
+
+    for px in pixels.chunks_exact_mut(channels) {
+        let average: [u8; 3] = mapping[&color(px)];
+        px[..color_channels].copy_from_slice(&average[..color_channels]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::comp::fixtures::sample;
+
+    const METHODS: [QuantizeMethod; 4] = [QuantizeMethod::BitDepth, QuantizeMethod::Ordered, QuantizeMethod::FloydSteinberg, QuantizeMethod::Palette];
+
+    fn quantized(method: QuantizeMethod, quality: u8, channels: usize) -> (Vec<u8>, LossyOptions) {
+        let options: LossyOptions = LossyOptions { method, quality };
+        let mut pixels: Vec<u8> = sample(40, 24, channels);
+        quantize(&mut pixels, 40, channels, &options);
+        (pixels, options)
+    }
+
+    #[test]
+    fn values_stay_on_the_requested_levels() {
+        for method in METHODS {
+            for channels in 1..=4 {
+                let (pixels, options) = quantized(method, 30, channels);
+                let color_channels: usize = color_channels(channels);
+
+                if method == QuantizeMethod::Palette {
+                    let colors: HashSet<&[u8]> = pixels.chunks_exact(channels).map(|px| &px[..color_channels]).collect();
+                    assert!(colors.len() <= options.palette_size(), "{:?} {}", method, channels);
+                } else {
+                    let off_level: bool = pixels
+                        .chunks_exact(channels)
+                        .flat_map(|px| &px[..color_channels])
+                        .any(|v| snap(*v as f32, options.bits()) != *v);
+                    assert!(!off_level, "{:?} {}", method, channels);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_is_untouched() {
+        for method in METHODS {
+            for channels in [2, 4] {
+                let (pixels, _) = quantized(method, 0, channels);
+                let alpha = |pixels: &[u8]| pixels.chunks_exact(channels).map(|px| px[channels - 1]).collect::<Vec<u8>>();
+                assert_eq!(alpha(&pixels), alpha(&sample(40, 24, channels)), "{:?} {}", method, channels);
+            }
+        }
+    }
+
+    #[test]
+    fn full_quality_keeps_every_value() {
+        for method in [QuantizeMethod::BitDepth, QuantizeMethod::Ordered, QuantizeMethod::FloydSteinberg] {
+            assert_eq!(quantized(method, MAX_QUALITY, 3).0, sample(40, 24, 3), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn width_not_dividing_the_buffer() {
+        let options: LossyOptions = LossyOptions { method: QuantizeMethod::FloydSteinberg, quality: 0 };
+        for width in [0, 7, 1000] {
+            let mut pixels: Vec<u8> = sample(10, 1, 3);
+            quantize(&mut pixels, width, 3, &options);
+            // One bit per channel leaves only 0 and 255, the last partial row included.
+            assert!(pixels.iter().all(|v| *v == 0 || *v == 255), "width {}", width);
+        }
+
+        let mut pixels: Vec<u8> = sample(10, 1, 3);
+        quantize(&mut pixels, 7, 3, &LossyOptions { method: QuantizeMethod::Ordered, quality: 0 });
+        assert!(pixels.iter().all(|v| *v == 0 || *v == 255));
+    }
+
+    #[test]
+    fn methods_parse() {
+        assert_eq!("floyd-steinberg".parse::<QuantizeMethod>().unwrap(), QuantizeMethod::FloydSteinberg);
+        assert!(matches!("median".parse::<QuantizeMethod>(), Err(QoiError::InvalidArgument(_))));
+        assert_eq!((LossyOptions { method: QuantizeMethod::Palette, quality: 0 }).palette_size(), 2);
+        assert_eq!((LossyOptions { method: QuantizeMethod::BitDepth, quality: 255 }).bits(), 8);
+    }
+}
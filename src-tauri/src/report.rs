@@ -0,0 +1,77 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::QualityReport;
+use crate::stats::EncodingStats;
+use crate::format::FormatChoice;
+
+// Summary of a single compressed Data, returned to the frontend.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CompressionReport {
+    pub path: String,
+    pub encoded_path: String,
+    pub raw_size: usize,
+    // QOI stream size, before any second stage. Split files count both planes, as 'encoded_size' does.
+    pub qoi_size: usize,
+    // Size of the written file, with the low plane of a split file.
+    pub encoded_size: usize,
+    // Source compared against the decoded output, None when verification was skipped.
+    pub quality: Option<QualityReport>,
+    // Metadata sidecar, when the source had any.
+    pub sidecar: Option<String>,
+    // Low bytes of a 16 bit source, when it was split into two planes.
+    pub low_plane: Option<String>,
+    // Single channel source, encoded through the gray fast path.
+    pub grayscale: bool,
+    // Bytes the optimize mode saved against the plain encoder.
+    pub optimize_saved: Option<usize>,
+    // Op counts and bytes of the written stream, when asked for.
+    pub stats: Option<EncodingStats>,
+    // Formats tried by auto format and the one written.
+    pub format: Option<FormatChoice>,
+    // Precision lost on the way, shown to the user.
+    pub warnings: Vec<String>,
+}
+
+impl CompressionReport {
+    pub fn ratio(&self) -> f64 {
+        self.raw_size as f64 / self.encoded_size.max(1) as f64
+    }
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} -> {}", self.path, self.encoded_path)?;
+        writeln!(f, "  {} -> {} bytes (ratio {:.2})", self.raw_size, self.encoded_size, self.ratio())?;
+        if self.qoi_size != self.encoded_size {
+            let saved: f64 = 100.0 * (1.0 - self.encoded_size as f64 / self.qoi_size.max(1) as f64);
+            writeln!(f, "  QOI stream {} bytes, second stage saved {:.1}%", self.qoi_size, saved)?;
+        }
+        match &self.quality {
+            Some(quality) => write!(f, "  {}", quality)?,
+            None => write!(f, "  not verified")?,
+        }
+        if let Some(sidecar) = &self.sidecar {
+            write!(f, "\n  metadata kept in {}", sidecar)?;
+        }
+        if self.grayscale {
+            write!(f, "\n  grayscale source")?;
+        }
+        if let Some(saved) = self.optimize_saved {
+            write!(f, "\n  optimize saved {} bytes", saved)?;
+        }
+        if let Some(low_plane) = &self.low_plane {
+            write!(f, "\n  low bytes kept in {}", low_plane)?;
+        }
+        if let Some(format) = &self.format {
+            write!(f, "\n  format {}", format)?;
+        }
+        if let Some(stats) = &self.stats {
+            write!(f, "\n  encoding stats:\n{}", stats)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
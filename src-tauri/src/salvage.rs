@@ -0,0 +1,345 @@
+/*
+Salvage decoder for damaged QOI files: decodes every chunk it can instead of giving up,
+fills the pixels it never reached and reports where the damage begins.
+Every byte sequence decodes to something, so a flipped byte in the middle of the stream only shows
+at the end mark or in the trailer checksum; truncated files are located exactly.
+*/
+
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::consts::*;
+use crate::comp::encode_image;
+use crate::colorspace::ColorSpace;
+use crate::container::Trailer;
+use crate::entropy;
+use crate::optimize::ColorTransform;
+use crate::pixel::{Pixel, Zero};
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+
+// Magenta, so the filled area stands out.
+pub const DEFAULT_FILL: [u8; 3] = [255, 0, 255];
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Corruption {
+    // Byte of the stream where the damage was found.
+    pub offset: usize,
+    // First pixel that couldn't be decoded, None when every pixel was but the stream doesn't end right.
+    pub pixel: Option<usize>,
+    pub reason: String,
+}
+
+#[derive(Clone)]
+pub struct Salvaged {
+    pub file: QoiFile,
+    // Pixels decoded from the stream, the rest is filled.
+    pub recovered: usize,
+    pub corruption: Option<Corruption>,
+}
+
+// Parses "<r>,<g>,<b>".
+pub fn parse_color(value: &str) -> Result<[u8; 3], QoiError> {
+    let invalid = || QoiError::InvalidArgument(format!("invalid color '{}', expected <r>,<g>,<b>", value));
+    let parts: Vec<u8> = value
+        .split(',')
+        .map(|c| c.trim().parse().map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>, QoiError>>()?;
+    parts.try_into().map_err(|_| invalid())
+}
+
+/*
+Decodes as much of the QOI stream in 'bytes' as possible, pixels past the damage are set to 'fill'.
+Only a header too short or too damaged to give the image size is an error.
+ */
+pub fn salvage(bytes: &[u8], path: PathBuf, fill: [u8; 3]) -> Result<Salvaged, QoiError> {
+    let (bytes, stage_error): (Cow<[u8]>, Option<String>) = entropy::unwrap_partial(bytes);
+    let bytes: &[u8] = &bytes;
+    // Where the stream ends early, a damaged second stage is the cause.
+    let truncated: String = match &stage_error {
+        Some(error) => format!("second stage stopped early: {}", error),
+        None => "stream is truncated".to_string(),
+    };
+    if bytes.len() < QOI_HEADER_SIZE {
+        return Err(QoiError::InvalidHeader(format!("only {} bytes, the header needs {} ({})", bytes.len(), QOI_HEADER_SIZE, truncated)));
+    }
+
+    let width: u32 = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let height: u32 = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let pixel_count: usize = width as usize * height as usize;
+    if pixel_count == 0 || pixel_count as u64 > (bytes.len() - QOI_HEADER_SIZE) as u64 * 62 {
+        return Err(QoiError::DimensionMismatch(format!("header gives {}x{}, too damaged to salvage", width, height)));
+    }
+
+    let channels: usize = if bytes[12] == 4 { 4 } else { CHANNELS as usize };
+    let mut pixels: Vec<u8> = vec![0; pixel_count * channels];
+
+    let mut corruption: Option<Corruption> = None;
+    let mut damage = |offset: usize, pixel: Option<usize>, reason: String| {
+        corruption.get_or_insert(Corruption { offset, pixel, reason });
+    };
+    if bytes[0..4] != QOI_MAGIC {
+        damage(0, None, format!("magic is {:?}", &bytes[0..4]));
+    }
+
+    let mut pos: usize = QOI_HEADER_SIZE;
+    let mut pixel: usize = 0;
+    let mut prev: Pixel = Pixel::zero();
+    let mut seen: [Pixel; 64] = [Pixel::INDEX_START; 64];
+
+    while pixel < pixel_count {
+        let Some(&byte) = bytes.get(pos) else {
+            damage(pos, Some(pixel), truncated.clone());
+            break;
+        };
+        let size: usize = match byte {
+            QOI_OP_RGB => 4,
+            QOI_OP_RGBA => 5,
+            _ if byte & QOI_2BIT_TAG_MASK == QOI_OP_LUMA => 2,
+            _ => 1,
+        };
+        let Some(chunk) = bytes.get(pos..pos + size) else {
+            damage(pos, Some(pixel), truncated.clone());
+            break;
+        };
+
+        let mut length: usize = 1;
+        match byte {
+            QOI_OP_RGB => (prev.r, prev.g, prev.b) = (chunk[1], chunk[2], chunk[3]),
+            QOI_OP_RGBA => (prev.r, prev.g, prev.b, prev.a) = (chunk[1], chunk[2], chunk[3], chunk[4]),
+            _ => match byte & QOI_2BIT_TAG_MASK {
+                QOI_OP_INDEX => prev = seen[(byte & QOI_INDEX_VALUE_MASK) as usize],
+                QOI_OP_DIFF => {
+                    prev.r = prev.r.wrapping_add(((byte & QOI_RED_DIFF) >> 4).wrapping_sub(2));
+                    prev.g = prev.g.wrapping_add(((byte & QOI_GREEN_DIFF) >> 2).wrapping_sub(2));
+                    prev.b = prev.b.wrapping_add((byte & QOI_BLUE_DIFF).wrapping_sub(2));
+                }
+                QOI_OP_LUMA => {
+                    let diff_g: u8 = (byte & QOI_LUMA_DG).wrapping_sub(32);
+                    prev.r = prev.r.wrapping_add(((chunk[1] & QOI_LUMA_DRDG_MASK) >> 4).wrapping_sub(8).wrapping_add(diff_g));
+                    prev.g = prev.g.wrapping_add(diff_g);
+                    prev.b = prev.b.wrapping_add((chunk[1] & QOI_LUMA_DBDG_MASK).wrapping_sub(8).wrapping_add(diff_g));
+                }
+                _ => length = (byte & QOI_RUN_LENGTH_MASK) as usize + 1,
+            },
+        }
+        seen[prev.hash() % seen.len()] = prev;
+
+        // A run past the last pixel means the chunks were out of step before it.
+        if pixel + length > pixel_count {
+            damage(pos, None, format!("run goes {} pixels past the image", pixel + length - pixel_count));
+            length = pixel_count - pixel;
+        }
+        for out in pixels[pixel * channels..(pixel + length) * channels].chunks_exact_mut(channels) {
+            out[..CHANNELS as usize].copy_from_slice(&prev.to_bytes());
+            if channels == 4 {
+                out[3] = prev.a;
+            }
+        }
+
+        pos += size;
+        pixel += length;
+    }
+
+    let recovered: usize = pixel;
+    let mut color_transform: Option<ColorTransform> = None;
+    if recovered == pixel_count {
+        if stage_error.is_some() {
+            damage(bytes.len(), None, truncated);
+        } else if bytes.get(pos..pos + QOI_END_MARK_SIZE) != Some(&QOI_END_MARK[..]) {
+            damage(pos, None, "end mark missing, chunks before it are damaged".to_string());
+        } else {
+            match Trailer::parse(&bytes[pos + QOI_END_MARK_SIZE..]) {
+                Ok(Some(trailer)) => {
+                    if trailer.verify(&pixels, channels).is_err() {
+                        damage(pos, None, "pixels don't match the trailer checksum".to_string());
+                    }
+                    color_transform = trailer.color_transform;
+                }
+                Ok(None) => (),
+                // A color transform it held is lost too, the pixels may stay transformed.
+                Err(error) => damage(pos + QOI_END_MARK_SIZE, None, format!("trailer unreadable, checksum not verified ({})", error)),
+            }
+        }
+    }
+
+    // The optimize mode's color transform is undone on the recovered pixels, the fill stays as given.
+    // A file cut before its trailer has lost it, its pixels stay transformed.
+    if let Some(transform) = color_transform {
+        transform.inverse(&mut pixels[..recovered * channels], channels);
+    }
+    for out in pixels[recovered * channels..].chunks_exact_mut(channels) {
+        out[..CHANNELS as usize].copy_from_slice(&fill);
+        if channels == 4 {
+            out[3] = 255;
+        }
+    }
+
+    let mut file: QoiFile = QoiFile {
+        path,
+        size: 0,
+        width,
+        height,
+        channels: channels as u8,
+        color_space: bytes[13],
+        pixels,
+        trailer: None,
+    };
+    file.set_size();
+    Ok(Salvaged { file, recovered, corruption })
+}
+
+impl Salvaged {
+
+    pub fn open(path: &Path, fill: [u8; 3]) -> Result<Self, QoiError> {
+        salvage(&std::fs::read(path)?, path.to_path_buf(), fill)
+    }
+
+    /*
+    Writes the recovered image to 'path' as a valid QOI file, returns the number of written bytes.
+     */
+    pub fn save(&self, path: &Path) -> Result<usize, QoiError> {
+        let file: &QoiFile = &self.file;
+        let color_space: ColorSpace = file.colorspace();
+        let out: Vec<u8> = encode_image(&file.pixels, file.channels as usize, file.width, file.height, color_space);
+        std::fs::write(path, &out)?;
+        Ok(out.len())
+    }
+}
+
+impl fmt::Display for Salvaged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: usize = self.file.width as usize * self.file.height as usize;
+        write!(f, "{}: recovered {} of {} pixels", self.file.path.display(), self.recovered, total)?;
+        match &self.corruption {
+            Some(corruption) => {
+                write!(f, "\n  damaged from byte {}: {}", corruption.offset, corruption.reason)?;
+                if let Some(pixel) = corruption.pixel {
+                    let width: usize = self.file.width as usize;
+                    write!(f, "\n  first lost pixel {} ({}, {})", pixel, pixel % width, pixel / width)?;
+                }
+            }
+            None => write!(f, "\n  no damage found")?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::fixtures::{self, temp_file};
+    use crate::container::pixel_crc;
+
+    fn sample() -> Vec<u8> {
+        fixtures::sample(20, 10, 3)
+    }
+
+    fn stream() -> Vec<u8> {
+        fixtures::stream(20, 10)
+    }
+
+    fn trailer(pixels: &[u8], color_transform: Option<ColorTransform>) -> Vec<u8> {
+        let trailer: Trailer = Trailer {
+            pixel_crc: pixel_crc(pixels, 3),
+            source_hash: None,
+            entries: Vec::new(),
+            color_transform,
+        };
+        trailer.to_bytes()
+    }
+
+    #[test]
+    fn intact_stream_is_recovered_whole() {
+        let salvaged: Salvaged = salvage(&stream(), PathBuf::from("intact.qoi"), DEFAULT_FILL).unwrap();
+        assert_eq!(salvaged.recovered, 200);
+        assert!(salvaged.corruption.is_none());
+        assert_eq!(salvaged.file.pixels, sample());
+    }
+
+    #[test]
+    fn truncated_stream_is_filled_from_the_cut() {
+        let bytes: Vec<u8> = stream();
+        let salvaged: Salvaged = salvage(&bytes[..bytes.len() / 2], PathBuf::from("cut.qoi"), [1, 2, 3]).unwrap();
+        let recovered: usize = salvaged.recovered;
+        assert!(recovered > 0 && recovered < 200);
+
+        let corruption: Corruption = salvaged.corruption.unwrap();
+        assert_eq!(corruption.pixel, Some(recovered));
+        assert_eq!(corruption.reason, "stream is truncated");
+        assert_eq!(salvaged.file.pixels[..recovered * 3], sample()[..recovered * 3]);
+        assert!(salvaged.file.pixels[recovered * 3..].chunks_exact(3).all(|p| p == [1, 2, 3]));
+    }
+
+    #[test]
+    fn trailer_checksum_and_transform_are_used() {
+        let mut bytes: Vec<u8> = stream();
+        bytes.extend(trailer(&sample(), None));
+        assert!(salvage(&bytes, PathBuf::new(), DEFAULT_FILL).unwrap().corruption.is_none());
+
+        let mut wrong: Vec<u8> = stream();
+        wrong.extend(trailer(&sample()[3..], None));
+        let corruption: Corruption = salvage(&wrong, PathBuf::new(), DEFAULT_FILL).unwrap().corruption.unwrap();
+        assert_eq!((corruption.pixel, corruption.reason.as_str()), (None, "pixels don't match the trailer checksum"));
+
+        // Transformed pixels come back in their original order.
+        let transform: ColorTransform = ColorTransform { order: [2, 0, 1], subtract_green: true };
+        let mut transformed: Vec<u8> = sample();
+        transform.forward(&mut transformed, 3);
+        let mut bytes: Vec<u8> = encode_image(&transformed, 3, 20, 10, ColorSpace::Srgb);
+        bytes.extend(trailer(&transformed, Some(transform)));
+        let salvaged: Salvaged = salvage(&bytes, PathBuf::new(), DEFAULT_FILL).unwrap();
+        assert!(salvaged.corruption.is_none());
+        assert_eq!(salvaged.file.pixels, sample());
+    }
+
+    #[test]
+    fn unreadable_trailer_is_reported() {
+        let mut bytes: Vec<u8> = stream();
+        let end: usize = bytes.len();
+        bytes.extend(trailer(&sample(), None));
+        bytes.truncate(bytes.len() - 2);
+
+        let salvaged: Salvaged = salvage(&bytes, PathBuf::new(), DEFAULT_FILL).unwrap();
+        assert_eq!(salvaged.recovered, 200);
+        let corruption: Corruption = salvaged.corruption.unwrap();
+        assert_eq!((corruption.offset, corruption.pixel), (end, None));
+        assert!(corruption.reason.starts_with("trailer unreadable, checksum not verified"));
+    }
+
+    #[test]
+    fn unreadable_headers_and_missing_end_mark() {
+        let bytes: Vec<u8> = stream();
+        assert!(matches!(salvage(&bytes[..10], PathBuf::new(), DEFAULT_FILL), Err(QoiError::InvalidHeader(_))));
+
+        let mut huge: Vec<u8> = bytes.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(salvage(&huge, PathBuf::new(), DEFAULT_FILL), Err(QoiError::DimensionMismatch(_))));
+
+        let salvaged: Salvaged = salvage(&bytes[..bytes.len() - 2], PathBuf::new(), DEFAULT_FILL).unwrap();
+        assert_eq!(salvaged.recovered, 200);
+        assert!(salvaged.corruption.unwrap().reason.contains("end mark"));
+    }
+
+    #[test]
+    fn saved_file_opens_cleanly() {
+        let bytes: Vec<u8> = stream();
+        let path: PathBuf = temp_file("salvage", "cut.qoi");
+        let salvaged: Salvaged = salvage(&bytes[..bytes.len() / 3], PathBuf::new(), DEFAULT_FILL).unwrap();
+        salvaged.save(&path).unwrap();
+
+        let reopened: Salvaged = Salvaged::open(&path, DEFAULT_FILL).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(reopened.corruption.is_none());
+        assert_eq!(reopened.file.pixels, salvaged.file.pixels);
+    }
+
+    #[test]
+    fn fill_colors_parse() {
+        assert_eq!(parse_color("10, 20,30").unwrap(), [10, 20, 30]);
+        assert!(matches!(parse_color("10,20"), Err(QoiError::InvalidArgument(_))));
+        assert!(matches!(parse_color("10,20,300"), Err(QoiError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,367 @@
+/*
+Animated GIF/APNG support through a multi-frame QOI sequence container.
+Every frame is a complete QOI stream. A diff frame holds the byte-wise difference to the previous frame,
+so areas that don't move become long runs.
+
+Layout: QOI_SEQUENCE_MAGIC, width, height, frame count (big endian u32), colorspace byte,
+then for every frame its delay in ms (big endian u32), flags, the stream length (big endian u32) and the stream.
+*/
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write, Error};
+use std::path::{Path, PathBuf};
+use image::{AnimationDecoder, Delay, DynamicImage, Frame as ImageFrame, RgbImage, RgbaImage};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+
+use crate::consts::*;
+use crate::comp::{decode_bytes, encode_image};
+use crate::colorspace::{convert, ColorSpace};
+use crate::qoi_errror::QoiError;
+use crate::transform::{apply_all, ChannelMode, Transform};
+
+pub const SEQUENCE_HEADER_SIZE: usize = 17;
+
+const FRAME_HEADER_SIZE: usize = 9;
+const FLAG_DIFF: u8 = 0b00000001;
+
+// Single frame, RGB pixels of the whole canvas.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub delay_ms: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub color_space: u8,
+    pub frames: Vec<Frame>,
+}
+
+// Byte-wise difference of 'current' to 'previous', undone by 'apply_diff'.
+fn diff(current: &[u8], previous: &[u8]) -> Vec<u8> {
+    current.iter().zip(previous).map(|(c, p)| c.wrapping_sub(*p)).collect()
+}
+
+fn apply_diff(diff: &mut [u8], previous: &[u8]) {
+    diff.iter_mut().zip(previous).for_each(|(d, p)| *d = d.wrapping_add(*p));
+}
+
+impl Sequence {
+
+    /*
+    Reads every frame of the animated GIF or APNG at 'path', None when it isn't animated.
+    Frames are composed on the full canvas, alpha is dropped as the encoder does.
+     */
+    pub fn read(path: &Path) -> Result<Option<Self>, QoiError> {
+        let extension: String = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let reader = || -> Result<BufReader<File>, QoiError> { Ok(BufReader::new(File::open(path)?)) };
+
+        let frames: Vec<ImageFrame> = match extension.as_str() {
+            "gif" => GifDecoder::new(reader()?)?.into_frames().collect_frames()?,
+            "png" | "apng" => {
+                let decoder: PngDecoder<BufReader<File>> = PngDecoder::new(reader()?)?;
+                if !decoder.is_apng() {
+                    return Ok(None);
+                }
+                decoder.apng().into_frames().collect_frames()?
+            }
+            _ => return Ok(None),
+        };
+        if frames.len() < 2 {
+            return Ok(None);
+        }
+
+        let (width, height) = frames[0].buffer().dimensions();
+        let frames: Vec<Frame> = frames
+            .into_iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                Frame {
+                    delay_ms: numer / denom.max(1),
+                    pixels: frame.into_buffer().pixels().flat_map(|p| [p[0], p[1], p[2]]).collect(),
+                }
+            })
+            .collect();
+
+        Ok(Some(Self { path: path.to_path_buf(), width, height, color_space: QOI_SRGB, frames }))
+    }
+
+    // Runs 'transforms' on every frame, frames stay RGB whatever the channel mode.
+    pub fn transformed(mut self, transforms: &[Transform], channel_mode: ChannelMode) -> Self {
+        if transforms.is_empty() && channel_mode != ChannelMode::Gray {
+            return self;
+        }
+
+        // Every frame starts at the same size and ends at the same size.
+        let (width, height) = (self.width, self.height);
+        for frame in &mut self.frames {
+            let img: RgbImage = RgbImage::from_raw(width, height, std::mem::take(&mut frame.pixels)).unwrap();
+            let transformed: RgbImage = channel_mode.apply(apply_all(transforms, DynamicImage::ImageRgb8(img))).to_rgb8();
+            (self.width, self.height) = transformed.dimensions();
+            frame.pixels = transformed.into_raw();
+        }
+        self
+    }
+
+    pub fn raw_size(&self) -> usize {
+        self.frames.iter().map(|f| f.pixels.len()).sum()
+    }
+
+    // Every frame after another, as one flat RGB buffer.
+    pub fn flat_pixels(&self) -> Vec<u8> {
+        self.frames.iter().flat_map(|f| f.pixels.iter().copied()).collect()
+    }
+
+    /*
+    Encodes the sequence into 'buffer', returns the number of written bytes.
+    With 'frame_diff' a frame is stored as a diff whenever that is smaller than the frame itself.
+     */
+    pub fn encode<W: Write>(&self, color_space: ColorSpace, frame_diff: bool, buffer: &mut W) -> Result<usize, Error> {
+        let mut header: Vec<u8> = Vec::with_capacity(SEQUENCE_HEADER_SIZE);
+        header.extend_from_slice(&QOI_SEQUENCE_MAGIC);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        header.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        header.push(color_space.header_byte());
+        buffer.write_all(&header)?;
+
+        let channels: usize = CHANNELS as usize;
+        let mut written_bytes: usize = header.len();
+        let mut previous: Option<&Frame> = None;
+
+        for frame in &self.frames {
+            let mut flags: u8 = 0;
+            let mut stream: Vec<u8> = encode_image(&frame.pixels, channels, self.width, self.height, color_space);
+
+            if let (true, Some(previous)) = (frame_diff, previous) {
+                let diff_stream: Vec<u8> = encode_image(&diff(&frame.pixels, &previous.pixels), channels, self.width, self.height, color_space);
+                if diff_stream.len() < stream.len() {
+                    stream = diff_stream;
+                    flags |= FLAG_DIFF;
+                }
+            }
+
+            buffer.write_all(&frame.delay_ms.to_be_bytes())?;
+            buffer.write_all(&[flags])?;
+            buffer.write_all(&(stream.len() as u32).to_be_bytes())?;
+            buffer.write_all(&stream)?;
+            written_bytes += FRAME_HEADER_SIZE + stream.len();
+            previous = Some(frame);
+        }
+
+        buffer.flush()?;
+        Ok(written_bytes)
+    }
+
+    /*
+    Decodes the sequence container in 'bytes'.
+     */
+    pub fn decode(bytes: &[u8], path: PathBuf) -> Result<Self, QoiError> {
+        if bytes.len() < SEQUENCE_HEADER_SIZE || bytes[0..4] != QOI_SEQUENCE_MAGIC {
+            return Err(QoiError::InvalidHeader(format!("{:?}", &bytes[..bytes.len().min(4)])));
+        }
+
+        let read_u32 = |at: usize| -> Result<u32, QoiError> {
+            let field: &[u8] = bytes
+                .get(at..at + 4)
+                .ok_or_else(|| QoiError::InvalidHeader(format!("sequence is truncated at byte {}", at)))?;
+            Ok(u32::from_be_bytes([field[0], field[1], field[2], field[3]]))
+        };
+        let width: u32 = read_u32(4)?;
+        let height: u32 = read_u32(8)?;
+        let count: u32 = read_u32(12)?;
+        let color_space: u8 = bytes[16];
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut pos: usize = SEQUENCE_HEADER_SIZE;
+        for _ in 0..count {
+            let delay_ms: u32 = read_u32(pos)?;
+            let flags: u8 = bytes.get(pos + 4).copied().unwrap_or(0);
+            let len: usize = read_u32(pos + 5)? as usize;
+            let stream: &[u8] = bytes
+                .get(pos + FRAME_HEADER_SIZE..pos + FRAME_HEADER_SIZE + len)
+                .ok_or_else(|| QoiError::InvalidHeader(format!("frame at byte {} is truncated", pos)))?;
+
+            let mut pixels: Vec<u8> = decode_bytes(stream, path.clone())?.pixels;
+            if pixels.len() != width as usize * height as usize * CHANNELS as usize {
+                return Err(QoiError::DimensionMismatch(format!("frame at byte {} isn't {}x{}", pos, width, height)));
+            }
+            if flags & FLAG_DIFF != 0 {
+                let previous: &Frame = frames
+                    .last()
+                    .ok_or_else(|| QoiError::InvalidHeader("first frame is a diff".to_string()))?;
+                apply_diff(&mut pixels, &previous.pixels);
+            }
+
+            frames.push(Frame { delay_ms, pixels });
+            pos += FRAME_HEADER_SIZE + len;
+        }
+
+        Ok(Self { path, width, height, color_space, frames })
+    }
+
+    pub fn open(path: &Path) -> Result<Self, QoiError> {
+        Self::decode(&std::fs::read(path)?, path.to_path_buf())
+    }
+
+    // The file at 'path' is a sequence container.
+    pub fn is_sequence(path: &Path) -> bool {
+        let mut magic: [u8; 4] = [0; 4];
+        File::open(path)
+            .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic))
+            .map(|_| magic == QOI_SEQUENCE_MAGIC)
+            .unwrap_or(false)
+    }
+
+    // Frame pixels converted into 'color_space', with their delays.
+    fn converted_frames(&self, color_space: ColorSpace) -> impl Iterator<Item = (Vec<u8>, u32)> + '_ {
+        self.frames.iter().map(move |frame| {
+            let mut pixels: Vec<u8> = frame.pixels.clone();
+            convert(&mut pixels, CHANNELS as usize, ColorSpace::from_header(self.color_space), color_space);
+            (pixels, frame.delay_ms)
+        })
+    }
+
+    /*
+    Saves the sequence to 'path' as an animated GIF, or an APNG for ".png" and ".apng".
+     */
+    pub fn export(&self, path: &Path, color_space: ColorSpace) -> Result<(), QoiError> {
+        let extension: String = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+
+        match extension.as_str() {
+            "gif" => {
+                let mut encoder: GifEncoder<BufWriter<File>> = GifEncoder::new(writer);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(self.converted_frames(color_space).map(|(pixels, delay)| {
+                    let rgba: Vec<u8> = pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect();
+                    let img: RgbaImage = RgbaImage::from_raw(self.width, self.height, rgba).unwrap();
+                    ImageFrame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(delay, 1))
+                }))?;
+            }
+            "png" | "apng" => self.export_apng(writer, color_space)
+                .map_err(|e| QoiError::SavingError(e.to_string()))?,
+            _ => return Err(QoiError::InvalidArgument(format!("sequences export to GIF or APNG, not '{}'", extension))),
+        }
+        Ok(())
+    }
+
+    fn export_apng(&self, writer: BufWriter<File>, color_space: ColorSpace) -> Result<(), png::EncodingError> {
+        let mut encoder: png::Encoder<BufWriter<File>> = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+
+        let mut writer = encoder.write_header()?;
+        for (pixels, delay) in self.converted_frames(color_space) {
+            // APNG delays are a u16 fraction of a second.
+            writer.set_frame_delay(delay.min(u16::MAX as u32) as u16, 1000)?;
+            writer.write_image_data(&pixels)?;
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 20;
+    const HEIGHT: u32 = 12;
+
+    // A square moving over a still background, in colors a GIF palette keeps exactly.
+    fn sample() -> Sequence {
+        let frames: Vec<Frame> = (0..4)
+            .map(|i| Frame {
+                delay_ms: 40 + i * 10,
+                pixels: (0..WIDTH * HEIGHT)
+                    .flat_map(|p| {
+                        let (x, y) = (p % WIDTH, p / WIDTH);
+                        if (i * 3..i * 3 + 5).contains(&x) && (2..7).contains(&y) { [255, 0, 0] } else { [0, 0, (y * 16) as u8] }
+                    })
+                    .collect(),
+            })
+            .collect();
+        Sequence { path: PathBuf::new(), width: WIDTH, height: HEIGHT, color_space: QOI_SRGB, frames }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qross_sequence_{}_{}", std::process::id(), name))
+    }
+
+    fn encoded(sequence: &Sequence, frame_diff: bool) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let written: usize = sequence.encode(ColorSpace::Srgb, frame_diff, &mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+        bytes
+    }
+
+    #[test]
+    fn sequence_round_trip_with_and_without_diff_frames() {
+        let sequence: Sequence = sample();
+        for frame_diff in [false, true] {
+            let decoded: Sequence = Sequence::decode(&encoded(&sequence, frame_diff), PathBuf::new()).unwrap();
+            assert_eq!((decoded.width, decoded.height, decoded.frames.len()), (WIDTH, HEIGHT, 4));
+            for (decoded, frame) in decoded.frames.iter().zip(&sequence.frames) {
+                assert_eq!((decoded.delay_ms, &decoded.pixels), (frame.delay_ms, &frame.pixels));
+            }
+        }
+
+        // The still background becomes runs in the diff frames.
+        assert!(encoded(&sequence, true).len() < encoded(&sequence, false).len());
+    }
+
+    #[test]
+    fn damaged_sequences_are_rejected() {
+        let bytes: Vec<u8> = encoded(&sample(), true);
+
+        assert!(matches!(Sequence::decode(&bytes[..SEQUENCE_HEADER_SIZE - 1], PathBuf::new()), Err(QoiError::InvalidHeader(_))));
+        assert!(matches!(Sequence::decode(&bytes[..bytes.len() - 1], PathBuf::new()), Err(QoiError::InvalidHeader(_))));
+
+        let mut first_is_diff: Vec<u8> = bytes.clone();
+        first_is_diff[SEQUENCE_HEADER_SIZE + 4] = FLAG_DIFF;
+        assert!(matches!(Sequence::decode(&first_is_diff, PathBuf::new()), Err(QoiError::InvalidHeader(_))));
+
+        // Frames must have the size of the header.
+        let mut wider: Vec<u8> = bytes.clone();
+        wider[4..8].copy_from_slice(&(WIDTH + 1).to_be_bytes());
+        assert!(matches!(Sequence::decode(&wider, PathBuf::new()), Err(QoiError::DimensionMismatch(_))));
+    }
+
+    #[test]
+    fn gif_and_apng_export_read_back() {
+        let sequence: Sequence = sample();
+        for name in ["export.gif", "export.png"] {
+            let path: PathBuf = temp_file(name);
+            sequence.export(&path, ColorSpace::Srgb).unwrap();
+            assert!(!Sequence::is_sequence(&path));
+
+            let read: Sequence = Sequence::read(&path).unwrap().unwrap();
+            assert_eq!(read.frames.len(), 4, "{}", name);
+            assert_eq!(read.flat_pixels(), sequence.flat_pixels(), "{}", name);
+            assert_eq!(read.frames[1].delay_ms, 50, "{}", name);
+            std::fs::remove_file(path).ok();
+        }
+
+        assert!(matches!(sequence.export(&temp_file("export.qoi"), ColorSpace::Srgb), Err(QoiError::InvalidArgument(_))));
+        std::fs::remove_file(temp_file("export.qoi")).ok();
+    }
+
+    #[test]
+    fn still_images_are_not_sequences() {
+        let path: PathBuf = temp_file("still.png");
+        RgbImage::new(4, 4).save(&path).unwrap();
+        assert!(Sequence::read(&path).unwrap().is_none());
+        std::fs::remove_file(path).ok();
+    }
+}
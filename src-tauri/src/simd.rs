@@ -0,0 +1,265 @@
+/*
+SIMD helpers for the encoder's hot loop, picked automatically with a scalar fallback.
+Both helpers work on raw pixel bytes of any layout (1 to 4 channels) and must stay bit-identical to their scalar versions.
+*/
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::pixel::Pixel;
+
+// Pixels hashed at once by 'hash_block'.
+pub const HASH_BLOCK: usize = 64;
+
+// Smallest multiple of every pixel size (1 to 4) and of the SSE2 register size.
+const PATTERN_SIZE: usize = 48;
+
+// Runs scanned without vectors before switching to them.
+const SHORT_RUN: usize = 4;
+
+/*
+Returns the number of leading pixels of 'pixels' whose bytes equal 'raw' ('raw.len()' bytes per pixel).
+ */
+pub fn run_length(pixels: &[u8], raw: &[u8]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    // SSE2 is part of the x86_64 baseline.
+    let length: usize = unsafe { run_length_sse2(pixels, raw) };
+    #[cfg(not(target_arch = "x86_64"))]
+    let length: usize = run_length_scalar(pixels, raw);
+
+    debug_assert_eq!(length, run_length_scalar(pixels, raw));
+    length
+}
+
+pub fn run_length_scalar(pixels: &[u8], raw: &[u8]) -> usize {
+    pixels
+        .chunks_exact(raw.len())
+        .take_while(|px| *px == raw)
+        .count()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn run_length_sse2(pixels: &[u8], raw: &[u8]) -> usize {
+    let channels: usize = raw.len();
+
+    // Most runs are short, only long ones are worth the vectors.
+    let short: usize = run_length_scalar(&pixels[..pixels.len().min(SHORT_RUN * channels)], raw);
+    if short < SHORT_RUN {
+        return short;
+    }
+
+    let mut pattern: [u8; PATTERN_SIZE] = [0; PATTERN_SIZE];
+    pattern.chunks_exact_mut(channels).for_each(|p| p.copy_from_slice(raw));
+
+    let patterns: [__m128i; 3] = [
+        _mm_loadu_si128(pattern.as_ptr() as *const __m128i),
+        _mm_loadu_si128(pattern.as_ptr().add(16) as *const __m128i),
+        _mm_loadu_si128(pattern.as_ptr().add(32) as *const __m128i),
+    ];
+
+    // Count equal bytes 16 at a time, the pattern lines up with every pixel size.
+    let mut equal: usize = 0;
+    let mut mismatch: bool = false;
+    'blocks: for block in pixels.chunks_exact(PATTERN_SIZE) {
+        for (i, p) in patterns.iter().enumerate() {
+            let v: __m128i = _mm_loadu_si128(block.as_ptr().add(i * 16) as *const __m128i);
+            let mask: u32 = _mm_movemask_epi8(_mm_cmpeq_epi8(v, *p)) as u32;
+            if mask != 0xFFFF {
+                equal += mask.trailing_ones() as usize;
+                mismatch = true;
+                break 'blocks;
+            }
+            equal += 16;
+        }
+    }
+
+    // Whole pixels only, the scalar version finishes what is left after the last full block.
+    let pixels_equal: usize = equal / channels;
+    if mismatch {
+        return pixels_equal;
+    }
+    pixels_equal + run_length_scalar(&pixels[equal..], raw)
+}
+
+/*
+Writes Pixel::hash % 64 of up to HASH_BLOCK leading pixels of 'pixels' into 'hashes', returns the amount hashed.
+Layouts with less than 3 channels are gray. The encoder drops alpha, so it is hashed as 255.
+ */
+pub fn hash_block(pixels: &[u8], channels: usize, hashes: &mut [u8; HASH_BLOCK]) -> usize {
+    let count: usize = hash_block_fast(pixels, channels, hashes);
+
+    // Debug builds check the fast path against the scalar one.
+    #[cfg(debug_assertions)]
+    {
+        let mut expected: [u8; HASH_BLOCK] = [0; HASH_BLOCK];
+        let expected_count: usize = hash_block_scalar(pixels, channels, &mut expected);
+        assert_eq!(&hashes[..count], &expected[..expected_count]);
+    }
+    count
+}
+
+fn hash_block_fast(pixels: &[u8], channels: usize, hashes: &mut [u8; HASH_BLOCK]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if channels == 4 {
+            return unsafe { hash_block_rgba_sse2(pixels, hashes) };
+        }
+        if channels == 3 && is_x86_feature_detected!("ssse3") {
+            return unsafe { hash_block_rgb_ssse3(pixels, hashes) };
+        }
+    }
+    hash_block_scalar(pixels, channels, hashes)
+}
+
+#[inline(always)]
+fn hash_scalar(px: &[u8]) -> u8 {
+    let pixel: Pixel = if px.len() < 3 {
+        Pixel { r: px[0], g: px[0], b: px[0], a: 255 }
+    } else {
+        Pixel { r: px[0], g: px[1], b: px[2], a: 255 }
+    };
+    (pixel.hash() % 64) as u8
+}
+
+pub fn hash_block_scalar(pixels: &[u8], channels: usize, hashes: &mut [u8; HASH_BLOCK]) -> usize {
+    let mut count: usize = 0;
+    for (hash, px) in hashes.iter_mut().zip(pixels.chunks_exact(channels)) {
+        *hash = hash_scalar(px);
+        count += 1;
+    }
+    count
+}
+
+// Hashes 4 pixels held as RGBX in the 32 bit lanes of 'v', mirrors Pixel::hash with an alpha of 255.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn hash_lanes(v: __m128i) -> [u32; 4] {
+    let low: __m128i = _mm_set1_epi32(0xFF);
+
+    // Values stay below 2^16, so 16 bit multiplies are exact.
+    let r: __m128i = _mm_mullo_epi16(_mm_and_si128(v, low), _mm_set1_epi32(3));
+    let g: __m128i = _mm_mullo_epi16(_mm_and_si128(_mm_srli_epi32(v, 8), low), _mm_set1_epi32(5));
+    let b: __m128i = _mm_mullo_epi16(_mm_and_si128(_mm_srli_epi32(v, 16), low), _mm_set1_epi32(7));
+    let a: __m128i = _mm_set1_epi32(255 * 11);
+    let hash: __m128i = _mm_and_si128(_mm_add_epi32(_mm_add_epi32(_mm_add_epi32(r, g), b), a), _mm_set1_epi32(63));
+
+    let mut lanes: [u32; 4] = [0; 4];
+    _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, hash);
+    lanes
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn hash_block_rgba_sse2(pixels: &[u8], hashes: &mut [u8; HASH_BLOCK]) -> usize {
+    let mut count: usize = 0;
+    while count + 4 <= HASH_BLOCK && (count + 4) * 4 <= pixels.len() {
+        let v: __m128i = _mm_loadu_si128(pixels.as_ptr().add(count * 4) as *const __m128i);
+        for (i, hash) in hash_lanes(v).iter().enumerate() {
+            hashes[count + i] = *hash as u8;
+        }
+        count += 4;
+    }
+
+    // Remaining pixels.
+    for px in pixels[count * 4..].chunks_exact(4).take(HASH_BLOCK - count) {
+        hashes[count] = hash_scalar(px);
+        count += 1;
+    }
+    count
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2,ssse3")]
+unsafe fn hash_block_rgb_ssse3(pixels: &[u8], hashes: &mut [u8; HASH_BLOCK]) -> usize {
+    // Spreads 4 RGB pixels into RGBX lanes.
+    let spread: __m128i = _mm_setr_epi8(0, 1, 2, -1, 3, 4, 5, -1, 6, 7, 8, -1, 9, 10, 11, -1);
+
+    let mut count: usize = 0;
+    // A 16 byte load reads 4 pixels and 4 bytes past them.
+    while count + 4 <= HASH_BLOCK && count * 3 + 16 <= pixels.len() {
+        let v: __m128i = _mm_loadu_si128(pixels.as_ptr().add(count * 3) as *const __m128i);
+        for (i, hash) in hash_lanes(_mm_shuffle_epi8(v, spread)).iter().enumerate() {
+            hashes[count + i] = *hash as u8;
+        }
+        count += 4;
+    }
+
+    for px in pixels[count * 3..].chunks_exact(3).take(HASH_BLOCK - count) {
+        hashes[count] = hash_scalar(px);
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // The vector path when the target has one, whatever the build profile.
+    fn fast_run_length(pixels: &[u8], raw: &[u8]) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        return unsafe { run_length_sse2(pixels, raw) };
+        #[cfg(not(target_arch = "x86_64"))]
+        return run_length_scalar(pixels, raw);
+    }
+
+    fn fast_hashes(pixels: &[u8], channels: usize) -> Vec<u8> {
+        let mut hashes: [u8; HASH_BLOCK] = [0; HASH_BLOCK];
+        let count: usize = hash_block_fast(pixels, channels, &mut hashes);
+        hashes[..count].to_vec()
+    }
+
+    fn scalar_hashes(pixels: &[u8], channels: usize) -> Vec<u8> {
+        let mut hashes: [u8; HASH_BLOCK] = [0; HASH_BLOCK];
+        let count: usize = hash_block_scalar(pixels, channels, &mut hashes);
+        hashes[..count].to_vec()
+    }
+
+    // Around the vector and pattern sizes, and runs below, at and past the 62 pixel limit of a run chunk.
+    const LENGTHS: [usize; 16] = [0, 1, 3, 4, 5, 15, 16, 17, 47, 48, 49, 61, 62, 63, 124, 125];
+
+    #[test]
+    fn run_length_matches_scalar_on_edge_lengths() {
+        for channels in 1..=4 {
+            let raw: Vec<u8> = (0..channels as u8).map(|c| 10 + c).collect();
+            for length in LENGTHS {
+                let run: Vec<u8> = raw.repeat(length);
+                assert_eq!(fast_run_length(&run, &raw), length);
+
+                // The run broken on every byte of the next pixel, with more equal pixels after it.
+                for byte in 0..channels {
+                    let mut broken: Vec<u8> = [run.as_slice(), &raw, &raw.repeat(20)].concat();
+                    broken[length * channels + byte] ^= 0x80;
+                    assert_eq!(fast_run_length(&broken, &raw), run_length_scalar(&broken, &raw));
+                    assert_eq!(fast_run_length(&broken, &raw), length, "{} channels, run of {}", channels, length);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hash_block_matches_scalar_on_edge_lengths() {
+        for channels in 1..=4 {
+            for count in LENGTHS.iter().copied().chain([HASH_BLOCK - 1, HASH_BLOCK, HASH_BLOCK + 1]) {
+                let pixels: Vec<u8> = (0..count * channels).map(|i| (i * 151 + 7) as u8).collect();
+                assert_eq!(fast_hashes(&pixels, channels), scalar_hashes(&pixels, channels), "{} channels, {} pixels", channels, count);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn run_length_matches_scalar(channels in 1usize..=4, raw in any::<[u8; 4]>(), length in 0usize..200, tail in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let raw: &[u8] = &raw[..channels];
+            let pixels: Vec<u8> = [raw.repeat(length), tail].concat();
+            prop_assert_eq!(fast_run_length(&pixels, raw), run_length_scalar(&pixels, raw));
+        }
+
+        #[test]
+        fn hash_block_matches_scalar(channels in 1usize..=4, pixels in proptest::collection::vec(any::<u8>(), 0..400)) {
+            prop_assert_eq!(fast_hashes(&pixels, channels), scalar_hashes(&pixels, channels));
+        }
+    }
+}
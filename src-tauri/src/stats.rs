@@ -0,0 +1,220 @@
+/*
+Encoding statistics: what every chunk of a QOI stream is spent on, to explain why one image compresses better than another.
+They are read back from the written stream, so the encoder's hot loop stays untouched.
+*/
+
+use std::borrow::Cow;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::consts::*;
+use crate::entropy;
+use crate::qoi_errror::QoiError;
+
+const HISTOGRAM_BUCKETS: usize = 8;
+const HISTOGRAM_WIDTH: usize = 40;
+
+// Amount and total bytes of a single op.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct OpStats {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl OpStats {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct EncodingStats {
+    pub run: OpStats,
+    pub index: OpStats,
+    pub diff: OpStats,
+    pub luma: OpStats,
+    pub rgb: OpStats,
+    pub rgba: OpStats,
+    // Pixels covered by RUN chunks.
+    pub run_pixels: usize,
+    // Chunk bytes of every row, a chunk counts for the row its first pixel is in.
+    pub row_bytes: Vec<usize>,
+}
+
+impl EncodingStats {
+
+    /*
+    Walks the chunks of the QOI stream in 'bytes', zstd and LZ4 compressed streams are unwrapped first.
+     */
+    pub fn from_stream(bytes: &[u8]) -> Result<Self, QoiError> {
+        let bytes: Cow<[u8]> = entropy::unwrap(bytes)?;
+        if bytes.len() < QOI_HEADER_SIZE || bytes[0..4] != QOI_MAGIC {
+            return Err(QoiError::InvalidHeader(format!("{:?}", &bytes[..bytes.len().min(4)])));
+        }
+
+        let width: usize = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let height: usize = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let pixel_count: usize = width * height;
+
+        // A run chunk holds at most 62 pixels, a header promising more than the bytes can hold is corrupt.
+        if pixel_count > (bytes.len() - QOI_HEADER_SIZE) * 62 {
+            return Err(QoiError::DimensionMismatch(format!("{}x{} pixels don't fit in {} bytes", width, height, bytes.len())));
+        }
+
+        let mut stats: Self = Self { row_bytes: vec![0; height], ..Default::default() };
+        let mut pos: usize = QOI_HEADER_SIZE;
+        let mut pixel: usize = 0;
+
+        while pixel < pixel_count {
+            let byte: u8 = *bytes
+                .get(pos)
+                .ok_or_else(|| QoiError::InvalidEndMark(format!("stream ends after {} of {} pixels", pixel, pixel_count)))?;
+
+            let (size, pixels): (usize, usize) = match byte {
+                QOI_OP_RGB => {
+                    stats.rgb.add(4);
+                    (4, 1)
+                }
+                QOI_OP_RGBA => {
+                    stats.rgba.add(5);
+                    (5, 1)
+                }
+                _ => match byte & QOI_2BIT_TAG_MASK {
+                    QOI_OP_INDEX => {
+                        stats.index.add(1);
+                        (1, 1)
+                    }
+                    QOI_OP_DIFF => {
+                        stats.diff.add(1);
+                        (1, 1)
+                    }
+                    QOI_OP_LUMA => {
+                        stats.luma.add(2);
+                        (2, 1)
+                    }
+                    _ => {
+                        let length: usize = (byte & QOI_RUN_LENGTH_MASK) as usize + 1;
+                        stats.run.add(1);
+                        stats.run_pixels += length;
+                        (1, length)
+                    }
+                },
+            };
+
+            stats.row_bytes[pixel / width] += size;
+            pos += size;
+            pixel += pixels;
+        }
+
+        Ok(stats)
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.ops().iter().map(|(_, op)| op.count).sum()
+    }
+
+    // Share of the chunks, runs aside, that were found in the index.
+    pub fn index_hit_rate(&self) -> f64 {
+        let lookups: usize = self.chunk_count() - self.run.count;
+        self.index.count as f64 / lookups.max(1) as f64
+    }
+
+    pub fn average_run_length(&self) -> f64 {
+        self.run_pixels as f64 / self.run.count.max(1) as f64
+    }
+
+    fn ops(&self) -> [(&'static str, &OpStats); 6] {
+        [
+            ("RUN", &self.run),
+            ("INDEX", &self.index),
+            ("DIFF", &self.diff),
+            ("LUMA", &self.luma),
+            ("RGB", &self.rgb),
+            ("RGBA", &self.rgba),
+        ]
+    }
+
+    // Rows counted into equal ranges of bytes per row, with the range start.
+    pub fn histogram(&self) -> Vec<(usize, usize)> {
+        let min: usize = self.row_bytes.iter().copied().min().unwrap_or(0);
+        let max: usize = self.row_bytes.iter().copied().max().unwrap_or(0);
+        let step: usize = ((max - min) / HISTOGRAM_BUCKETS + 1).max(1);
+
+        let mut buckets: Vec<(usize, usize)> = (0..HISTOGRAM_BUCKETS).map(|b| (min + b * step, 0)).collect();
+        for bytes in &self.row_bytes {
+            buckets[((bytes - min) / step).min(HISTOGRAM_BUCKETS - 1)].1 += 1;
+        }
+        buckets
+    }
+}
+
+impl fmt::Display for EncodingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: usize = self.ops().iter().map(|(_, op)| op.bytes).sum();
+        for (name, op) in self.ops() {
+            let share: f64 = 100.0 * op.bytes as f64 / total.max(1) as f64;
+            writeln!(f, "  {:<6} {:>10} chunks {:>12} bytes {:>5.1}%", name, op.count, op.bytes, share)?;
+        }
+        writeln!(f, "  index hit rate {:.1}%, average run {:.2} pixels", 100.0 * self.index_hit_rate(), self.average_run_length())?;
+
+        write!(f, "  bytes per row:")?;
+        let histogram: Vec<(usize, usize)> = self.histogram();
+        let most: usize = histogram.iter().map(|(_, rows)| *rows).max().unwrap_or(0).max(1);
+        for (start, rows) in histogram {
+            write!(f, "\n  {:>8}+ {:>7} {}", start, rows, "#".repeat(rows * HISTOGRAM_WIDTH / most))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::entropy::{Entropy, EntropyWriter};
+
+    // 3x2 image: a run of 2 and a DIFF on the first row, LUMA, RGB and INDEX on the second.
+    fn stream() -> Vec<u8> {
+        let mut bytes: Vec<u8> = [QOI_MAGIC.as_slice(), &3u32.to_be_bytes(), &2u32.to_be_bytes(), &[3, 0]].concat();
+        bytes.extend_from_slice(&[QOI_OP_RUN | 1, QOI_OP_DIFF | 0b101010, QOI_OP_LUMA | 40, 0x88, QOI_OP_RGB, 1, 2, 3, QOI_OP_INDEX | 5]);
+        bytes.extend_from_slice(&QOI_END_MARK);
+        bytes
+    }
+
+    #[test]
+    fn every_op_is_counted() {
+        let stats: EncodingStats = EncodingStats::from_stream(&stream()).unwrap();
+
+        let counts: Vec<(usize, usize)> = stats.ops().iter().map(|(_, op)| (op.count, op.bytes)).collect();
+        assert_eq!(counts, [(1, 1), (1, 1), (1, 1), (1, 2), (1, 4), (0, 0)]);
+        assert_eq!((stats.chunk_count(), stats.run_pixels), (5, 2));
+        assert_eq!(stats.row_bytes, [2, 7]);
+        assert_eq!(stats.index_hit_rate(), 0.25);
+        assert_eq!(stats.average_run_length(), 2.0);
+
+        let rows: usize = stats.histogram().iter().map(|(_, rows)| rows).sum();
+        assert_eq!(rows, 2);
+    }
+
+    #[test]
+    fn compressed_streams_give_the_same_stats() {
+        let mut writer: EntropyWriter<Vec<u8>> = EntropyWriter::new(Vec::new(), Some(Entropy::Lz4)).unwrap();
+        writer.write_all(&stream()).unwrap();
+
+        let compressed: EncodingStats = EncodingStats::from_stream(&writer.finish().unwrap()).unwrap();
+        assert_eq!(compressed.row_bytes, EncodingStats::from_stream(&stream()).unwrap().row_bytes);
+    }
+
+    #[test]
+    fn damaged_streams_are_rejected() {
+        let bytes: Vec<u8> = stream();
+        assert!(matches!(EncodingStats::from_stream(&bytes[..QOI_HEADER_SIZE + 3]), Err(QoiError::InvalidEndMark(_))));
+        assert!(matches!(EncodingStats::from_stream(&bytes[..3]), Err(QoiError::InvalidHeader(_))));
+
+        // The header can't make it allocate a counter for every promised row.
+        let mut huge: Vec<u8> = bytes.clone();
+        huge[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(EncodingStats::from_stream(&huge), Err(QoiError::DimensionMismatch(_))));
+    }
+}
@@ -0,0 +1,98 @@
+/*
+Parallel QOI encoding of a single image.
+The image is split into horizontal stripes which are encoded on different threads and stitched together.
+Every stripe after the first is encoded detached (see 'encode_chunks'),
+so the decoder's state at the boundary doesn't matter and the output stays a valid QOI stream.
+*/
+
+use rayon::prelude::*;
+use std::io::{Write, Error};
+
+use crate::consts::*;
+use crate::comp::{encode_chunks, max_encoded_size, write_header};
+use crate::colorspace::ColorSpace;
+
+// Stripes smaller than this aren't worth a thread.
+pub const MIN_STRIPE_ROWS: u32 = 64;
+
+// Rows per stripe, so every rayon thread gets about one stripe.
+pub fn stripe_rows(height: u32) -> u32 {
+    let threads: u32 = rayon::current_num_threads() as u32;
+    height.div_ceil(threads.max(1)).max(MIN_STRIPE_ROWS)
+}
+
+/*
+Encodes 'pixels' ('channels' bytes per pixel) into 'buffer' using stripes of 'rows' rows, returns the number of written bytes.
+ */
+pub fn encode_striped<W: Write>(pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace, rows: u32, buffer: &mut W) -> Result<usize, Error> {
+    let stripe_size: usize = width as usize * rows.max(1) as usize * channels;
+
+    let stripes: Vec<Vec<u8>> = pixels
+        .par_chunks(stripe_size.max(1))
+        .enumerate()
+        .map(|(i, stripe)| {
+            let mut out: Vec<u8> = vec![0; max_encoded_size(stripe.len() / channels)];
+            let len: usize = encode_chunks(stripe, channels, i > 0, &mut out, 0);
+            out.truncate(len);
+            out
+        })
+        .collect();
+
+    let mut header: [u8; QOI_HEADER_SIZE] = [0; QOI_HEADER_SIZE];
+    write_header(&mut header, width, height, color_space);
+
+    let mut written_bytes: usize = 0;
+    let mut write = |chunk: &[u8]| {
+        written_bytes += chunk.len();
+        buffer.write_all(chunk)
+    };
+
+    write(&header)?;
+    for stripe in &stripes {
+        write(stripe)?;
+    }
+    write(&QOI_END_MARK)?;
+
+    buffer.flush()?;
+    Ok(written_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::comp::decode_bytes;
+    use crate::comp::fixtures::sample;
+
+    fn round_trip(width: u32, height: u32, rows: u32) {
+        let pixels: Vec<u8> = sample(width, height, 3);
+        let mut bytes: Vec<u8> = Vec::new();
+        let written: usize = encode_striped(&pixels, 3, width, height, ColorSpace::Srgb, rows, &mut bytes).unwrap();
+
+        assert_eq!(written, bytes.len());
+        assert_eq!(decode_bytes(&bytes, PathBuf::new()).unwrap().pixels, pixels, "{}x{} in stripes of {}", width, height, rows);
+    }
+
+    #[test]
+    fn height_not_divisible_by_the_stripes() {
+        round_trip(30, 100, 7);
+        round_trip(30, 10, 3);
+    }
+
+    #[test]
+    fn single_row() {
+        round_trip(50, 1, 1);
+        round_trip(50, 1, stripe_rows(1));
+        round_trip(1, 9, 1);
+    }
+
+    #[test]
+    fn more_stripes_than_rows() {
+        // A stripe per row, stripes taller than the image, and the zero row count read as one.
+        round_trip(12, 5, 1);
+        round_trip(12, 5, 64);
+        round_trip(12, 5, 0);
+        assert!(stripe_rows(5) >= MIN_STRIPE_ROWS);
+        assert!(stripe_rows(100_000) as usize * rayon::current_num_threads() >= 100_000);
+    }
+}
@@ -0,0 +1,318 @@
+/*
+Tiled container for random access into huge images.
+The image is cut into square tiles, each one a complete QOI stream, found through an offset table.
+Reading a region only seeks to and decodes the tiles it overlaps.
+
+Layout: QOI_TILED_MAGIC, width, height, tile size (big endian u32), colorspace byte,
+then tile count + 1 big endian u64 offsets (the last one is the end of the file), then the tiles row by row.
+*/
+
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write, Error};
+use std::path::{Path, PathBuf};
+
+use crate::consts::*;
+use crate::comp::{decode_bytes, encode_image};
+use crate::colorspace::ColorSpace;
+use crate::qoi_file::QoiFile;
+use crate::qoi_errror::QoiError;
+
+pub const TILED_HEADER_SIZE: usize = 17;
+pub const DEFAULT_TILE_SIZE: u32 = 256;
+
+const OFFSET_SIZE: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct TiledFile {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    pub color_space: u8,
+    // Start of every tile, plus the end of the last one.
+    pub offsets: Vec<u64>,
+}
+
+// Amount of tiles along both axes.
+fn tile_grid(width: u32, height: u32, tile_size: u32) -> (u32, u32) {
+    (width.div_ceil(tile_size), height.div_ceil(tile_size))
+}
+
+/*
+Encodes 'pixels' ('channels' bytes per pixel) as a tiled container into 'buffer', returns the number of written bytes.
+Tiles are encoded in parallel.
+ */
+pub fn encode_tiled<W: Write>(pixels: &[u8], channels: usize, width: u32, height: u32, color_space: ColorSpace, tile_size: u32, buffer: &mut W) -> Result<usize, Error> {
+    let tile_size: u32 = tile_size.max(1);
+    let (tiles_x, tiles_y) = tile_grid(width, height, tile_size);
+
+    let tiles: Vec<Vec<u8>> = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .map(|i| {
+            let x: u32 = (i % tiles_x) * tile_size;
+            let y: u32 = (i / tiles_x) * tile_size;
+            let tile_width: u32 = tile_size.min(width - x);
+            let tile_height: u32 = tile_size.min(height - y);
+
+            // Copy the rows of the tile into a buffer of its own.
+            let row_size: usize = tile_width as usize * channels;
+            let mut tile: Vec<u8> = Vec::with_capacity(row_size * tile_height as usize);
+            for row in y..y + tile_height {
+                let start: usize = (row as usize * width as usize + x as usize) * channels;
+                tile.extend_from_slice(&pixels[start..start + row_size]);
+            }
+
+            encode_image(&tile, channels, tile_width, tile_height, color_space)
+        })
+        .collect();
+
+    let mut header: Vec<u8> = Vec::with_capacity(TILED_HEADER_SIZE + (tiles.len() + 1) * OFFSET_SIZE);
+    header.extend_from_slice(&QOI_TILED_MAGIC);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&tile_size.to_be_bytes());
+    header.push(color_space.header_byte());
+
+    let mut offset: u64 = (TILED_HEADER_SIZE + (tiles.len() + 1) * OFFSET_SIZE) as u64;
+    for tile in &tiles {
+        header.extend_from_slice(&offset.to_be_bytes());
+        offset += tile.len() as u64;
+    }
+    header.extend_from_slice(&offset.to_be_bytes());
+
+    buffer.write_all(&header)?;
+    for tile in &tiles {
+        buffer.write_all(tile)?;
+    }
+    buffer.flush()?;
+    Ok(offset as usize)
+}
+
+impl TiledFile {
+
+    /*
+    Reads the header and offset table of the tiled file at 'path', no tile is decoded.
+     */
+    pub fn open(path: &Path) -> Result<Self, QoiError> {
+        let mut file: File = File::open(path)?;
+        let file_size: u64 = file.metadata()?.len();
+
+        let mut header: [u8; TILED_HEADER_SIZE] = [0; TILED_HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if header[0..4] != QOI_TILED_MAGIC {
+            return Err(QoiError::InvalidHeader(format!("{:?}", &header[0..4])));
+        }
+
+        let read_u32 = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        let width: u32 = read_u32(4);
+        let height: u32 = read_u32(8);
+        let tile_size: u32 = read_u32(12);
+        if tile_size == 0 {
+            return Err(QoiError::InvalidHeader("tile size of 0".to_string()));
+        }
+
+        // The offset table must fit in the file, a damaged header can't make it allocate more.
+        let (tiles_x, tiles_y) = tile_grid(width, height, tile_size);
+        let table_size: Option<u64> = (tiles_x as u64)
+            .checked_mul(tiles_y as u64)
+            .and_then(|tiles| tiles.checked_add(1))
+            .and_then(|offsets| offsets.checked_mul(OFFSET_SIZE as u64))
+            .filter(|size| *size <= file_size.saturating_sub(TILED_HEADER_SIZE as u64));
+        let Some(table_size) = table_size else {
+            return Err(QoiError::InvalidHeader(format!(
+                "{}x{} image in tiles of {} needs an offset table larger than the file", width, height, tile_size
+            )));
+        };
+        let mut table: Vec<u8> = vec![0; table_size as usize];
+        file.read_exact(&mut table)?;
+
+        let offsets: Vec<u64> = table
+            .chunks_exact(OFFSET_SIZE)
+            .map(|o| u64::from_be_bytes([o[0], o[1], o[2], o[3], o[4], o[5], o[6], o[7]]))
+            .collect();
+        let ordered: bool = offsets.windows(2).all(|w| w[0] <= w[1]);
+        if !ordered || offsets.last().copied().unwrap_or(0) > file_size {
+            return Err(QoiError::InvalidHeader("offset table points outside of the file".to_string()));
+        }
+
+        Ok(Self { path: path.to_path_buf(), width, height, tile_size, color_space: header[16], offsets })
+    }
+
+    pub fn tiles_x(&self) -> u32 {
+        tile_grid(self.width, self.height, self.tile_size).0
+    }
+
+    /*
+    Decodes the tile at column 'tx' and row 'ty'.
+    Its header must give the size the grid expects, edge tiles are cut by the image borders.
+     */
+    pub fn read_tile(&self, tx: u32, ty: u32) -> Result<QoiFile, QoiError> {
+        let (tiles_x, tiles_y) = tile_grid(self.width, self.height, self.tile_size);
+        if tx >= tiles_x || ty >= tiles_y {
+            return Err(QoiError::DimensionMismatch(format!("tile ({}, {}) is outside of the {}x{} grid", tx, ty, tiles_x, tiles_y)));
+        }
+        let index: usize = ty as usize * tiles_x as usize + tx as usize;
+        let start: u64 = self.offsets[index];
+        let mut bytes: Vec<u8> = vec![0; (self.offsets[index + 1] - start) as usize];
+
+        let mut file: File = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut bytes)?;
+        let tile: QoiFile = decode_bytes(&bytes, self.path.clone())?;
+
+        let expected_width: u32 = self.tile_size.min(self.width - tx * self.tile_size);
+        let expected_height: u32 = self.tile_size.min(self.height - ty * self.tile_size);
+        if (tile.width, tile.height) != (expected_width, expected_height) {
+            return Err(QoiError::DimensionMismatch(format!(
+                "tile ({}, {}) is {}x{}, the grid expects {}x{}", tx, ty, tile.width, tile.height, expected_width, expected_height
+            )));
+        }
+        Ok(tile)
+    }
+
+    /*
+    Decodes the 'width' x 'height' region whose top left corner is ('x', 'y'), only reading the tiles it overlaps.
+     */
+    pub fn read_region(&self, x: u32, y: u32, width: u32, height: u32) -> Result<QoiFile, QoiError> {
+        // Right and bottom edges of the region, an edge past u32::MAX is outside too.
+        let edges: Option<(u32, u32)> = x
+            .checked_add(width)
+            .zip(y.checked_add(height))
+            .filter(|(right, bottom)| width > 0 && height > 0 && *right <= self.width && *bottom <= self.height);
+        let Some((right_edge, bottom_edge)) = edges else {
+            return Err(QoiError::DimensionMismatch(format!(
+                "region {}x{} at ({}, {}) is outside of the {}x{} image", width, height, x, y, self.width, self.height
+            )));
+        };
+
+        let channels: usize = CHANNELS as usize;
+        let mut pixels: Vec<u8> = vec![0; width as usize * height as usize * channels];
+
+        for ty in y / self.tile_size..=(bottom_edge - 1) / self.tile_size {
+            for tx in x / self.tile_size..=(right_edge - 1) / self.tile_size {
+                let tile: QoiFile = self.read_tile(tx, ty)?;
+                let tile_channels: usize = tile.channels as usize;
+                let (tile_x, tile_y) = (tx * self.tile_size, ty * self.tile_size);
+
+                // Overlap of the tile and the region, in image coordinates.
+                let (left, right) = (x.max(tile_x), right_edge.min(tile_x + tile.width));
+                let (top, bottom) = (y.max(tile_y), bottom_edge.min(tile_y + tile.height));
+
+                for row in top..bottom {
+                    let src_row: usize = (row - tile_y) as usize * tile.width as usize;
+                    let dst_row: usize = (row - y) as usize * width as usize;
+                    for col in left..right {
+                        let src: usize = (src_row + (col - tile_x) as usize) * tile_channels;
+                        let dst: usize = (dst_row + (col - x) as usize) * channels;
+                        pixels[dst..dst + channels].copy_from_slice(&tile.pixels[src..src + channels]);
+                    }
+                }
+            }
+        }
+
+        let mut region: QoiFile = QoiFile {
+            path: self.path.clone(),
+            size: 0,
+            width,
+            height,
+            channels: channels as u8,
+            color_space: self.color_space,
+            pixels,
+            trailer: None,
+        };
+        region.set_size();
+        Ok(region)
+    }
+
+    // Decodes every tile into a single image.
+    pub fn read_all(&self) -> Result<QoiFile, QoiError> {
+        self.read_region(0, 0, self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 23;
+    const HEIGHT: u32 = 17;
+
+    fn sample() -> Vec<u8> {
+        (0..WIDTH * HEIGHT * 3).map(|i| (i * 31 % 253) as u8).collect()
+    }
+
+    // Writes 'sample' in tiles of 'tile_size' to a file of its own.
+    fn tiled(name: &str, tile_size: u32) -> PathBuf {
+        let path: PathBuf = std::env::temp_dir().join(format!("qross_tiles_{}_{}.qoit", std::process::id(), name));
+        let mut bytes: Vec<u8> = Vec::new();
+        let written: usize = encode_tiled(&sample(), 3, WIDTH, HEIGHT, ColorSpace::Srgb, tile_size, &mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn regions_across_tiles_match_the_source() {
+        let path: PathBuf = tiled("regions", 8);
+        let file: TiledFile = TiledFile::open(&path).unwrap();
+        assert_eq!((file.tiles_x(), file.offsets.len()), (3, 3 * 3 + 1));
+        assert_eq!(file.read_all().unwrap().pixels, sample());
+
+        let source: Vec<u8> = sample();
+        for (x, y, width, height) in [(0, 0, 1, 1), (5, 3, 10, 9), (7, 7, 2, 2), (16, 8, 7, 9), (22, 16, 1, 1)] {
+            let region: QoiFile = file.read_region(x, y, width, height).unwrap();
+            let expected: Vec<u8> = (y..y + height)
+                .flat_map(|row| {
+                    let start: usize = (row * WIDTH + x) as usize * 3;
+                    source[start..start + width as usize * 3].to_vec()
+                })
+                .collect();
+            assert_eq!(region.pixels, expected, "region {}x{} at ({}, {})", width, height, x, y);
+        }
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn regions_and_tiles_outside_the_image_are_rejected() {
+        let path: PathBuf = tiled("outside", 8);
+        let file: TiledFile = TiledFile::open(&path).unwrap();
+
+        for (x, y, width, height) in [(0, 0, 0, 1), (20, 0, 4, 1), (0, 10, 1, 8), (u32::MAX, 0, 2, 1), (0, 1, 1, u32::MAX)] {
+            assert!(matches!(file.read_region(x, y, width, height), Err(QoiError::DimensionMismatch(_))));
+        }
+        assert!(matches!(file.read_tile(3, 0), Err(QoiError::DimensionMismatch(_))));
+
+        // A tile whose header disagrees with the grid: the last column is 7 pixels wide, a 24 pixel image expects 8.
+        let wider: TiledFile = TiledFile { width: 24, ..file };
+        assert!(matches!(wider.read_tile(2, 0), Err(QoiError::DimensionMismatch(_))));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn damaged_headers_are_rejected() {
+        let path: PathBuf = tiled("damaged", 8);
+        let bytes: Vec<u8> = std::fs::read(&path).unwrap();
+
+        let mut wrong_magic: Vec<u8> = bytes.clone();
+        wrong_magic[0] ^= 0xFF;
+        std::fs::write(&path, &wrong_magic).unwrap();
+        assert!(matches!(TiledFile::open(&path), Err(QoiError::InvalidHeader(_))));
+
+        // Tiles of 1 pixel on a huge image need a table far larger than the file.
+        let mut huge: Vec<u8> = bytes.clone();
+        huge[4..16].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 1]);
+        std::fs::write(&path, &huge).unwrap();
+        assert!(matches!(TiledFile::open(&path), Err(QoiError::InvalidHeader(_))));
+
+        let mut no_tile_size: Vec<u8> = bytes.clone();
+        no_tile_size[12..16].copy_from_slice(&[0; 4]);
+        std::fs::write(&path, &no_tile_size).unwrap();
+        assert!(matches!(TiledFile::open(&path), Err(QoiError::InvalidHeader(_))));
+
+        // The last offset points past the end of the file.
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(TiledFile::open(&path), Err(QoiError::InvalidHeader(_))));
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,272 @@
+/*
+Transform pipeline applied to every image between opening it and encoding it, in the given order.
+On the command line a transform is written as 'name=value', e.g. "fit=1920x1080:lanczos3" or "crop=10,20,640x480".
+*/
+
+use std::str::FromStr;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+
+use crate::qoi_errror::QoiError;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl Filter {
+    pub fn filter_type(&self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "triangle" => Ok(Filter::Triangle),
+            "catmull-rom" => Ok(Filter::CatmullRom),
+            "gaussian" => Ok(Filter::Gaussian),
+            "lanczos3" => Ok(Filter::Lanczos3),
+            _ => Err(QoiError::InvalidArgument(format!("unknown filter '{}'", s))),
+        }
+    }
+}
+
+// Layout of the encoded image.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ChannelMode {
+    // Whatever the source has.
+    #[default]
+    Auto,
+    Rgb,
+    Gray,
+}
+
+impl ChannelMode {
+
+    // Converts 'img' to the mode's layout, alpha is kept and high bit depths stay.
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let alpha: bool = img.color().has_alpha();
+        let high_depth: bool = img.color().bytes_per_pixel() / img.color().channel_count() > 1;
+
+        match (self, alpha, high_depth) {
+            (ChannelMode::Auto, _, _) => img,
+            (ChannelMode::Rgb, _, _) if img.color().has_color() => img,
+            (ChannelMode::Rgb, false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+            (ChannelMode::Rgb, true, false) => DynamicImage::ImageRgba8(img.to_rgba8()),
+            (ChannelMode::Rgb, false, true) => DynamicImage::ImageRgb16(img.to_rgb16()),
+            (ChannelMode::Rgb, true, true) => DynamicImage::ImageRgba16(img.to_rgba16()),
+            (ChannelMode::Gray, _, _) if !img.color().has_color() => img,
+            (ChannelMode::Gray, false, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+            (ChannelMode::Gray, true, false) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+            (ChannelMode::Gray, false, true) => DynamicImage::ImageLuma16(img.to_luma16()),
+            (ChannelMode::Gray, true, true) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        }
+    }
+}
+
+impl FromStr for ChannelMode {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ChannelMode::Auto),
+            "rgb" => Ok(ChannelMode::Rgb),
+            "gray" => Ok(ChannelMode::Gray),
+            _ => Err(QoiError::InvalidArgument(format!("unknown channel mode '{}'", s))),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Transform {
+    // Exactly 'width' x 'height', ignoring the aspect ratio.
+    Resize { width: u32, height: u32, filter: Filter },
+    // Largest size fitting inside 'width' x 'height', keeping the aspect ratio.
+    Fit { width: u32, height: u32, filter: Filter },
+    // Covers 'width' x 'height' keeping the aspect ratio, the overflow is cropped around the center.
+    Fill { width: u32, height: u32, filter: Filter },
+    // Clamped to the image.
+    Crop { x: u32, y: u32, width: u32, height: u32 },
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    StripAlpha,
+}
+
+impl Transform {
+
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Transform::Resize { width, height, filter } => img.resize_exact(*width, *height, filter.filter_type()),
+            Transform::Fit { width, height, filter } => img.resize(*width, *height, filter.filter_type()),
+            Transform::Fill { width, height, filter } => img.resize_to_fill(*width, *height, filter.filter_type()),
+            Transform::Crop { x, y, width, height } => img.crop_imm(*x, *y, *width, *height),
+            Transform::Rotate90 => img.rotate90(),
+            Transform::Rotate180 => img.rotate180(),
+            Transform::Rotate270 => img.rotate270(),
+            Transform::FlipHorizontal => img.fliph(),
+            Transform::FlipVertical => img.flipv(),
+            Transform::StripAlpha => strip_alpha(img),
+        }
+    }
+}
+
+// Drops the alpha channel, keeping 8/16 bit and gray layouts as they are.
+fn strip_alpha(img: DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLuma8(img.to_luma8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLuma16(img.to_luma16()),
+        DynamicImage::ImageRgba8(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgb32F(img.to_rgb32f()),
+        _ => img,
+    }
+}
+
+/*
+Runs every transform of 'transforms' on 'img', in order.
+ */
+pub fn apply_all(transforms: &[Transform], img: DynamicImage) -> DynamicImage {
+    transforms.iter().fold(img, |img, transform| transform.apply(img))
+}
+
+// Parses "<width>x<height>", both above 0.
+fn parse_size(value: &str) -> Result<(u32, u32), QoiError> {
+    let invalid = || QoiError::InvalidArgument(format!("invalid size '{}', expected <width>x<height>", value));
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+// Parses "<width>x<height>[:<filter>]".
+fn parse_scaling(value: &str) -> Result<(u32, u32, Filter), QoiError> {
+    let (size, filter) = match value.split_once(':') {
+        Some((size, filter)) => (size, filter.parse()?),
+        None => (value, Filter::default()),
+    };
+    let (width, height) = parse_size(size)?;
+    Ok((width, height, filter))
+}
+
+impl FromStr for Transform {
+    type Err = QoiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').unwrap_or((s, ""));
+        let invalid = || QoiError::InvalidArgument(format!("invalid transform '{}'", s));
+
+        match name {
+            "resize" => parse_scaling(value).map(|(width, height, filter)| Transform::Resize { width, height, filter }),
+            "fit" => parse_scaling(value).map(|(width, height, filter)| Transform::Fit { width, height, filter }),
+            "fill" => parse_scaling(value).map(|(width, height, filter)| Transform::Fill { width, height, filter }),
+            "crop" => {
+                // "<x>,<y>,<width>x<height>"
+                let mut parts = value.splitn(3, ',');
+                let x: u32 = parts.next().and_then(|x| x.parse().ok()).ok_or_else(invalid)?;
+                let y: u32 = parts.next().and_then(|y| y.parse().ok()).ok_or_else(invalid)?;
+                let (width, height) = parse_size(parts.next().ok_or_else(invalid)?)?;
+                Ok(Transform::Crop { x, y, width, height })
+            }
+            "rotate" => match value {
+                "90" => Ok(Transform::Rotate90),
+                "180" => Ok(Transform::Rotate180),
+                "270" => Ok(Transform::Rotate270),
+                _ => Err(invalid()),
+            },
+            "flip" => match value {
+                "h" => Ok(Transform::FlipHorizontal),
+                "v" => Ok(Transform::FlipVertical),
+                _ => Err(invalid()),
+            },
+            "strip-alpha" => Ok(Transform::StripAlpha),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    fn sample() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 20, |x, y| Rgba([x as u8, y as u8, 7, 128])))
+    }
+
+    #[test]
+    fn parsed_transforms_give_the_expected_sizes() {
+        let cases: [(&str, (u32, u32)); 8] = [
+            ("resize=10x30:nearest", (10, 30)),
+            ("fit=10x10", (10, 5)),
+            ("fill=10x10:triangle", (10, 10)),
+            ("crop=30,5,20x10", (10, 10)),
+            ("rotate=90", (20, 40)),
+            ("rotate=180", (40, 20)),
+            ("flip=h", (40, 20)),
+            ("strip-alpha", (40, 20)),
+        ];
+        for (text, size) in cases {
+            let transform: Transform = text.parse().unwrap();
+            assert_eq!(transform.apply(sample()).dimensions(), size, "{}", text);
+        }
+    }
+
+    #[test]
+    fn pixels_move_where_expected() {
+        let img: DynamicImage = sample();
+        assert_eq!(Transform::FlipHorizontal.apply(img.clone()).get_pixel(0, 3), img.get_pixel(39, 3));
+        assert_eq!(Transform::FlipVertical.apply(img.clone()).get_pixel(2, 0), img.get_pixel(2, 19));
+        assert_eq!(Transform::Rotate90.apply(img.clone()).get_pixel(19, 0), img.get_pixel(0, 0));
+        assert_eq!(Transform::Crop { x: 5, y: 6, width: 3, height: 3 }.apply(img.clone()).get_pixel(0, 0), img.get_pixel(5, 6));
+
+        let stripped: DynamicImage = Transform::StripAlpha.apply(img);
+        assert!(!stripped.color().has_alpha());
+    }
+
+    #[test]
+    fn transforms_run_in_order() {
+        let transforms: Vec<Transform> = vec!["crop=0,0,10x20".parse().unwrap(), Transform::Rotate90];
+        assert_eq!(apply_all(&transforms, sample()).dimensions(), (20, 10));
+        assert_eq!(apply_all(&[], sample()), sample());
+    }
+
+    #[test]
+    fn channel_modes_keep_alpha_and_depth() {
+        let gray: DynamicImage = ChannelMode::Gray.apply(sample());
+        assert!(!gray.color().has_color() && gray.color().has_alpha());
+        assert!(ChannelMode::Rgb.apply(gray).color().has_color());
+
+        let wide_gray: DynamicImage = ChannelMode::Gray.apply(DynamicImage::new_rgb16(2, 2));
+        assert_eq!(wide_gray.color(), image::ColorType::L16);
+    }
+
+    #[test]
+    fn bad_transforms_are_rejected() {
+        for text in ["resize=10", "resize=0x10", "fit=10x10:bicubic", "crop=1,2", "crop=a,2,3x3", "rotate=45", "flip=x", "blur=2"] {
+            assert!(matches!(text.parse::<Transform>(), Err(QoiError::InvalidArgument(_))), "{}", text);
+        }
+        assert!(matches!("cmyk".parse::<ChannelMode>(), Err(QoiError::InvalidArgument(_))));
+    }
+}
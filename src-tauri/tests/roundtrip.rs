@@ -0,0 +1,226 @@
+/*
+Property based round trip tests of the codec: decode(encode(x)) == x on random and adversarial images,
+cross-checked against the 'qoi' crate (a port of the reference implementation) and the image crate's QOI codec.
+Run with 'cargo test --test roundtrip'.
+*/
+
+use std::path::PathBuf;
+use image::{ColorType, ImageEncoder, ImageFormat};
+use image::codecs::qoi::QoiEncoder;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use image_compressor::comp::{decode_bytes, encode_image};
+use image_compressor::colorspace::ColorSpace;
+use image_compressor::stripes::encode_striped;
+
+// Longest run a single QOI_OP_RUN chunk holds.
+const MAX_RUN: usize = 62;
+
+#[derive(Clone, Debug)]
+struct TestImage {
+    width: u32,
+    height: u32,
+    // RGB, 3 bytes per pixel.
+    pixels: Vec<u8>,
+}
+
+impl TestImage {
+    fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        Self { width, height, pixels }
+    }
+
+    // Single row of 'pixels'.
+    fn row(pixels: Vec<u8>) -> Self {
+        Self::new((pixels.len() / 3) as u32, 1, pixels)
+    }
+}
+
+// Square-ish sizes, single rows and single columns.
+fn dimensions() -> impl Strategy<Value = (u32, u32)> {
+    prop_oneof![
+        (1u32..=64, 1u32..=64),
+        (1u32..=300).prop_map(|n| (n, 1)),
+        (1u32..=300).prop_map(|n| (1, n)),
+    ]
+}
+
+fn noise() -> impl Strategy<Value = TestImage> {
+    dimensions().prop_flat_map(|(width, height)| {
+        vec(any::<u8>(), (width * height * 3) as usize).prop_map(move |pixels| TestImage::new(width, height, pixels))
+    })
+}
+
+fn solid() -> impl Strategy<Value = TestImage> {
+    (dimensions(), any::<[u8; 3]>()).prop_map(|((width, height), color)| {
+        TestImage::new(width, height, color.repeat((width * height) as usize))
+    })
+}
+
+// Every channel moves by a small wrapping step per pixel, the LUMA and DIFF chunks' territory.
+fn gradient() -> impl Strategy<Value = TestImage> {
+    (dimensions(), any::<[u8; 3]>(), [-40i16..=40, -40i16..=40, -40i16..=40]).prop_map(|((width, height), start, step)| {
+        let pixels: Vec<u8> = (0..(width * height) as i16)
+            .flat_map(|i| (0..3).map(move |c| (start[c] as i16).wrapping_add(step[c].wrapping_mul(i)) as u8))
+            .collect();
+        TestImage::new(width, height, pixels)
+    })
+}
+
+// Runs whose lengths sit around the 62 pixel limit of a single chunk.
+fn runs() -> impl Strategy<Value = TestImage> {
+    let length = prop_oneof![
+        Just(1usize), Just(MAX_RUN - 1), Just(MAX_RUN), Just(MAX_RUN + 1),
+        Just(2 * MAX_RUN), Just(2 * MAX_RUN + 1), 1usize..200,
+    ];
+    vec((any::<[u8; 3]>(), length), 1..12).prop_map(|runs| {
+        TestImage::row(runs.iter().flat_map(|(color, length)| color.repeat(*length)).collect())
+    })
+}
+
+/*
+Colors sharing one index slot. Moving red by 5 and green by -3 keeps 3r + 5g + 7b + 11a the same mod 64,
+wrapping included since 256 * 3 and 256 * 5 are multiples of 64.
+ */
+fn index_collisions() -> impl Strategy<Value = TestImage> {
+    (any::<[u8; 3]>(), vec(0u8..8, 1..2000)).prop_map(|([r, g, b], picks)| {
+        let pixels: Vec<u8> = picks
+            .iter()
+            .flat_map(|k| [r.wrapping_add(5 * k), g.wrapping_sub(3 * k), b])
+            .collect();
+        TestImage::row(pixels)
+    })
+}
+
+// A handful of colors repeated at random, most pixels are index hits.
+fn palette() -> impl Strategy<Value = TestImage> {
+    (dimensions(), vec(any::<[u8; 3]>(), 1..6)).prop_flat_map(|((width, height), colors)| {
+        vec(0..colors.len(), (width * height) as usize).prop_map(move |picks| {
+            TestImage::new(width, height, picks.iter().flat_map(|i| colors[*i]).collect())
+        })
+    })
+}
+
+fn any_image() -> impl Strategy<Value = TestImage> {
+    prop_oneof![noise(), solid(), gradient(), runs(), index_collisions(), palette()]
+}
+
+fn encode(image: &TestImage) -> Vec<u8> {
+    encode_image(&image.pixels, 3, image.width, image.height, ColorSpace::Srgb)
+}
+
+/*
+Decoder following qoi.h line by line: the index starts as all zeros, alpha included, the previous pixel as opaque black.
+Returns the pixels with the channels given in the header.
+ */
+fn reference_decode(bytes: &[u8]) -> Vec<u8> {
+    let width: usize = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let height: usize = u32::from_be_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let channels: usize = bytes[12] as usize;
+
+    let mut index: [[u8; 4]; 64] = [[0; 4]; 64];
+    let mut px: [u8; 4] = [0, 0, 0, 255];
+    let mut run: usize = 0;
+    let mut p: usize = 14;
+    let mut pixels: Vec<u8> = Vec::with_capacity(width * height * channels);
+
+    for _ in 0..width * height {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let b1: u8 = bytes[p];
+            p += 1;
+            if b1 == 0xFE {
+                px[..3].copy_from_slice(&bytes[p..p + 3]);
+                p += 3;
+            } else if b1 == 0xFF {
+                px.copy_from_slice(&bytes[p..p + 4]);
+                p += 4;
+            } else if b1 & 0xC0 == 0x00 {
+                px = index[b1 as usize];
+            } else if b1 & 0xC0 == 0x40 {
+                px[0] = px[0].wrapping_add((b1 >> 4) & 0x03).wrapping_sub(2);
+                px[1] = px[1].wrapping_add((b1 >> 2) & 0x03).wrapping_sub(2);
+                px[2] = px[2].wrapping_add(b1 & 0x03).wrapping_sub(2);
+            } else if b1 & 0xC0 == 0x80 {
+                let b2: u8 = bytes[p];
+                p += 1;
+                let vg: u8 = (b1 & 0x3F).wrapping_sub(32);
+                px[0] = px[0].wrapping_add(vg.wrapping_sub(8).wrapping_add((b2 >> 4) & 0x0F));
+                px[1] = px[1].wrapping_add(vg);
+                px[2] = px[2].wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 & 0x0F));
+            } else {
+                run = (b1 & 0x3F) as usize;
+            }
+            let hash: usize = (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64;
+            index[hash] = px;
+        }
+        pixels.extend_from_slice(&px[..channels]);
+    }
+    pixels
+}
+
+// Opaque black must not come from its index slot before the stream wrote it, the reference decoder holds transparent black there.
+#[test]
+fn black_is_not_indexed_before_its_slot_is_written() {
+    let pixels: Vec<u8> = [[255, 0, 0], [0, 0, 0], [1, 1, 1], [200, 50, 50], [1, 1, 1]].concat();
+    let encoded: Vec<u8> = encode_image(&pixels, 3, 5, 1, ColorSpace::Srgb);
+    assert_eq!(reference_decode(&encoded), pixels);
+    assert_eq!(decode_bytes(&encoded, PathBuf::new()).unwrap().pixels, pixels);
+}
+
+proptest! {
+    #[test]
+    fn decode_inverts_encode(image in any_image()) {
+        let decoded = decode_bytes(&encode(&image), PathBuf::new()).unwrap();
+        prop_assert_eq!((decoded.width, decoded.height, decoded.channels), (image.width, image.height, 3));
+        prop_assert_eq!(decoded.pixels, image.pixels);
+    }
+
+    #[test]
+    fn reference_decoder_reads_our_output(image in any_image()) {
+        let (header, pixels) = qoi::decode_to_vec(encode(&image)).unwrap();
+        prop_assert_eq!((header.width, header.height), (image.width, image.height));
+        prop_assert_eq!(pixels, image.pixels);
+    }
+
+    #[test]
+    fn reference_semantics_decoder_reads_our_output(image in any_image()) {
+        prop_assert_eq!(reference_decode(&encode(&image)), image.pixels);
+    }
+
+    #[test]
+    fn image_crate_reads_our_output(image in any_image()) {
+        let decoded = image::load_from_memory_with_format(&encode(&image), ImageFormat::Qoi).unwrap();
+        prop_assert_eq!(decoded.to_rgb8().into_raw(), image.pixels);
+    }
+
+    #[test]
+    fn we_read_the_image_crate_output(image in any_image()) {
+        let mut encoded: Vec<u8> = Vec::new();
+        QoiEncoder::new(&mut encoded).write_image(&image.pixels, image.width, image.height, ColorType::Rgb8).unwrap();
+        prop_assert_eq!(decode_bytes(&encoded, PathBuf::new()).unwrap().pixels, image.pixels);
+    }
+
+    #[test]
+    fn striped_output_decodes_to_the_source(image in any_image(), rows in 1u32..8) {
+        let mut encoded: Vec<u8> = Vec::new();
+        encode_striped(&image.pixels, 3, image.width, image.height, ColorSpace::Srgb, rows, &mut encoded).unwrap();
+        prop_assert_eq!(decode_bytes(&encoded, PathBuf::new()).unwrap().pixels, image.pixels);
+    }
+
+    // Gray layouts are expanded to RGB, alpha is dropped.
+    #[test]
+    fn gray_and_alpha_layouts_decode_to_rgb(image in any_image(), alpha in any::<u8>()) {
+        let gray: Vec<u8> = image.pixels.iter().step_by(3).copied().collect();
+        let gray_alpha: Vec<u8> = gray.iter().flat_map(|v| [*v, alpha]).collect();
+        let rgba: Vec<u8> = image.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], alpha]).collect();
+        let expanded: Vec<u8> = gray.iter().flat_map(|v| [*v; 3]).collect();
+
+        for (pixels, channels, expected) in [(&gray, 1, &expanded), (&gray_alpha, 2, &expanded), (&rgba, 4, &image.pixels)] {
+            let encoded: Vec<u8> = encode_image(pixels, channels, image.width, image.height, ColorSpace::Srgb);
+            prop_assert_eq!(&decode_bytes(&encoded, PathBuf::new()).unwrap().pixels, expected);
+            prop_assert_eq!(&qoi::decode_to_vec(&encoded).unwrap().1, expected);
+        }
+    }
+}
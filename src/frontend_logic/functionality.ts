@@ -1,7 +1,7 @@
 import { invoke } from "@tauri-apps/api/tauri";
 import { open } from "@tauri-apps/api/dialog";
 import { convertFileSrc } from '@tauri-apps/api/tauri';
-import { log, img } from "../main";
+import { log, img, comparison, transform_form } from "../main";
 
 
 const FILE_DIALOG_ARGS = {
@@ -22,7 +22,178 @@ export async function browse_file() {
     }
 }
 
-export async function compress() {
-    const saved_bits = await invoke("compress");
+// 'options' follows CompressOptions on the Rust side (lossy, transforms, ...), left out it uses the defaults.
+export async function compress(options?: object) {
+    const saved_bits = await invoke("compress", { options: options });
     console.log(saved_bits)
 }
+
+/*
+Transforms and channel mode picked in the form, as CompressOptions fields.
+Transforms are listed crop, resize, rotate, then flips, the Rust side applies them in list order.
+ */
+export function transform_options(): object {
+    if(!transform_form) {
+      return {};
+    }
+    const form = new FormData(transform_form);
+    const text = (name: string) => (form.get(name) as string | null) ?? "";
+    const number = (name: string) => parseInt(text(name), 10);
+
+    const transforms: (object | string)[] = [];
+
+    const crop = ["crop_x", "crop_y", "crop_width", "crop_height"].map(number);
+    if(crop.every(v => !isNaN(v))) {
+      const [x, y, width, height] = crop;
+      transforms.push({ Crop: { x: x, y: y, width: width, height: height } });
+    }
+
+    const [width, height] = [number("resize_width"), number("resize_height")];
+    if(text("resize") && !isNaN(width) && !isNaN(height)) {
+      transforms.push({ [text("resize")]: { width: width, height: height, filter: text("filter") } });
+    }
+
+    if(text("rotate")) {
+      transforms.push(text("rotate"));
+    }
+    if(form.has("flip_horizontal")) {
+      transforms.push("FlipHorizontal");
+    }
+    if(form.has("flip_vertical")) {
+      transforms.push("FlipVertical");
+    }
+
+    return { transforms: transforms, channel_mode: text("channel_mode") || "Auto" };
+}
+
+export async function metrics(source: string, output: string) {
+    const report = await invoke("metrics", { source: source, output: output });
+    console.log(report)
+}
+
+// Sizes and encode/decode times of every queued file as QOI, PNG and lossless WebP.
+export async function compare(iterations?: number) {
+    const comparisons = await invoke("compare", { iterations: iterations });
+    console.log(comparisons)
+    return comparisons;
+}
+
+// One format of a comparison, as FormatResult on the Rust side.
+interface FormatResult {
+    codec: string;
+    size: number;
+    bytes_per_pixel: number;
+    encode_ms: number;
+    decode_ms: number;
+}
+
+interface Comparison {
+    path: string;
+    width: number;
+    height: number;
+    raw_size: number;
+    results: FormatResult[];
+}
+
+// A queued file that couldn't be read or compared.
+interface Failure {
+    path: string;
+    error: string;
+}
+
+// Runs the comparison and shows every file with its formats side by side, QOI first.
+export async function show_comparison(iterations?: number) {
+    const json = await compare(iterations) as string | null;
+    if(!comparison) {
+      return;
+    }
+    comparison.replaceChildren();
+    if(!json) {
+      comparison.textContent = "Comparison failed, is a file queued?";
+      return;
+    }
+
+    const { comparisons, failures } = JSON.parse(json) as { comparisons: Comparison[], failures: Failure[] };
+    for(const file of comparisons) {
+      comparison.appendChild(comparison_table(file));
+    }
+    for(const failure of failures) {
+      const line = document.createElement("p");
+      line.classList.add("failed");
+      line.textContent = `${failure.path}: ${failure.error}`;
+      comparison.appendChild(line);
+    }
+}
+
+function comparison_table(file: Comparison): HTMLTableElement {
+    const table = document.createElement("table");
+    table.createCaption().textContent = `${file.path} (${file.width}x${file.height}, ${file.raw_size} bytes raw)`;
+
+    const qoi_size = Math.max(file.results[0]?.size ?? 1, 1);
+    const smallest = Math.min(...file.results.map(r => r.size));
+    const fastest = Math.min(...file.results.map(r => r.encode_ms + r.decode_ms));
+
+    const rows: [string, (r: FormatResult) => string][] = [
+      ["Size", r => `${r.size} bytes`],
+      ["Of QOI", r => `${(100 * r.size / qoi_size).toFixed(1)}%`],
+      ["Bytes per pixel", r => r.bytes_per_pixel.toFixed(3)],
+      ["Encode", r => `${r.encode_ms.toFixed(2)} ms`],
+      ["Decode", r => `${r.decode_ms.toFixed(2)} ms`],
+    ];
+
+    const header = table.createTHead().insertRow();
+    header.insertCell().textContent = "";
+    for(const result of file.results) {
+      const cell = header.insertCell();
+      cell.textContent = result.codec;
+      cell.classList.toggle("smallest", result.size == smallest);
+      cell.classList.toggle("fastest", result.encode_ms + result.decode_ms == fastest);
+    }
+
+    const body = table.createTBody();
+    for(const [name, value] of rows) {
+      const row = body.insertRow();
+      row.insertCell().textContent = name;
+      for(const result of file.results) {
+        row.insertCell().textContent = value(result);
+      }
+    }
+    return table;
+}
+
+export async function inspect(file: string, options?: object) {
+    return await invoke("inspect", { file: file, options: options });
+}
+
+export async function salvage(file: string, output: string, fill?: string) {
+    return await invoke("salvage", { file: file, output: output, fill: fill });
+}
+
+export async function create_preset(name: string, options: object) {
+    return await invoke("create_preset", { name: name, options: options });
+}
+
+export async function edit_preset(name: string, options: object) {
+    return await invoke("edit_preset", { name: name, options: options });
+}
+
+export async function delete_preset(name: string) {
+    return await invoke("delete_preset", { name: name });
+}
+
+export async function list_presets() {
+    return JSON.parse(await invoke("list_presets") as string);
+}
+
+export async function apply_preset(name: string) {
+    const reports = await invoke("apply_preset", { name: name });
+    console.log(reports)
+}
+
+export async function export_presets(path: string) {
+    return await invoke("export_presets", { path: path });
+}
+
+export async function import_presets(path: string) {
+    return await invoke("import_presets", { path: path });
+}
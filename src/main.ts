@@ -1,20 +1,26 @@
-import { browse_file, compress} from "./frontend_logic/functionality";
+import { browse_file, compress, show_comparison, transform_options } from "./frontend_logic/functionality";
 
 export let log: HTMLElement | null;
 export let img: HTMLImageElement | null;
+export let comparison: HTMLElement | null;
+export let transform_form: HTMLFormElement | null;
 
 // Buttons
 let browse_btn: HTMLElement | null;
 let compress_btn: HTMLElement | null;
+let compare_btn: HTMLElement | null;
 
 window.addEventListener("DOMContentLoaded", () => {
   const querySelector = (id: string) => document.querySelector(id) as HTMLElement ;
 
   browse_btn = querySelector("#browse_btn")
   compress_btn = querySelector("#compress_btn")
+  compare_btn = querySelector("#compare_btn")
 
   log = querySelector("#result")
   img = document.querySelector("#image")
+  comparison = querySelector("#comparison")
+  transform_form = document.querySelector("#transform_form")
 
   browse_btn.addEventListener("click", (e: Event) => {
     e.preventDefault();
@@ -23,6 +29,11 @@ window.addEventListener("DOMContentLoaded", () => {
 
   compress_btn.addEventListener("click", (e: Event) => {
     e.preventDefault();
-    compress();
+    compress(transform_options());
+  });
+
+  compare_btn.addEventListener("click", (e: Event) => {
+    e.preventDefault();
+    show_comparison();
   });
 });